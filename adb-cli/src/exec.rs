@@ -0,0 +1,25 @@
+use adb_rs::exec::AdbExec;
+use adb_rs::AdbClient;
+
+pub fn run_out(cmd: &str) {
+  use std::io::{stdout, Write};
+
+  let mut conn = AdbClient::new("host::").connect("127.0.0.1:5555").unwrap();
+
+  let mut stream = conn.exec_stream(cmd).unwrap();
+  let stdout = stdout();
+  let mut stdout = stdout.lock();
+  while let Some(chunk) = stream.recv_chunk().unwrap() {
+    stdout.write_all(&chunk).unwrap();
+  }
+  stdout.flush().unwrap();
+}
+
+pub fn run_in(cmd: &str) {
+  use std::io::stdin;
+
+  let mut conn = AdbClient::new("host::").connect("127.0.0.1:5555").unwrap();
+
+  let stdin = stdin();
+  conn.exec_in(cmd, stdin.lock()).unwrap();
+}
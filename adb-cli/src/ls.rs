@@ -0,0 +1,69 @@
+use adb_rs::list::AdbList;
+use adb_rs::stat::RemoteMetadata;
+use adb_rs::AdbClient;
+
+pub fn run(remote_path: &str) {
+  let mut conn = AdbClient::new("host::").connect("127.0.0.1:5555").unwrap();
+
+  let mut entries: Vec<_> = conn.list_dir(remote_path).unwrap().collect();
+  entries.sort_by(|a, b| a.name().cmp(b.name()));
+
+  for entry in entries {
+    let metadata = entry.metadata();
+    println!(
+      "{} {:>10} {} {}",
+      format_mode(metadata),
+      metadata.len(),
+      format_mtime(metadata.mtime()),
+      entry.name()
+    );
+  }
+}
+
+fn format_mode(metadata: &RemoteMetadata) -> String {
+  let kind = if metadata.is_dir() {
+    'd'
+  } else if metadata.is_symlink() {
+    'l'
+  } else if metadata.is_file() {
+    '-'
+  } else {
+    '?'
+  };
+
+  let mut s = String::with_capacity(10);
+  s.push(kind);
+  for shift in &[6, 3, 0] {
+    let bits = (metadata.permissions() >> shift) & 0o7;
+    s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+    s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+    s.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+  }
+  s
+}
+
+// formats seconds since the epoch as "YYYY-MM-DD HH:MM" (UTC)
+fn format_mtime(mtime: i64) -> String {
+  let days = mtime.div_euclid(86400);
+  let secs = mtime.rem_euclid(86400);
+
+  // civil_from_days, see http://howardhinnant.github.io/date_algorithms.html
+  let z = days + 719_468;
+  let era = z.div_euclid(146_097);
+  let doe = z.rem_euclid(146_097);
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+  format!(
+    "{:04}-{:02}-{:02} {:02}:{:02}",
+    year,
+    month,
+    day,
+    secs / 3600,
+    secs % 3600 / 60
+  )
+}
@@ -3,12 +3,19 @@ extern crate log;
 
 use simplelog::*;
 
+use adb_rs::compression::Compression;
+use adb_rs::sync_dir::SyncDirOptions;
 use clap::load_yaml;
-use clap::App;
+use clap::{App, ArgMatches};
 
+mod exec;
+mod ls;
+mod progress;
+mod pull;
 mod push;
 mod server;
 mod shell;
+mod sync;
 
 fn main() {
   CombinedLogger::init(vec![
@@ -35,7 +42,73 @@ fn main() {
     return shell::run(m.value_of("CMD").unwrap());
   }
 
+  if let Some(m) = matches.subcommand_matches("exec-out") {
+    return exec::run_out(m.value_of("CMD").unwrap());
+  }
+
+  if let Some(m) = matches.subcommand_matches("exec-in") {
+    return exec::run_in(m.value_of("CMD").unwrap());
+  }
+
+  if let Some(m) = matches.subcommand_matches("ls") {
+    return ls::run(m.value_of("PATH").unwrap());
+  }
+
   if let Some(m) = matches.subcommand_matches("push") {
-    return push::run(m.value_of("SRC").unwrap(), m.value_of("DST").unwrap());
+    return push::run(
+      m.value_of("SRC").unwrap(),
+      m.value_of("DST").unwrap(),
+      m.is_present("follow-symlinks"),
+      m.is_present("verify"),
+      jobs(m),
+      compression(m),
+    );
+  }
+
+  if let Some(m) = matches.subcommand_matches("pull") {
+    if m.is_present("resume") {
+      return pull::resume(m.value_of("SRC").unwrap(), m.value_of("DST").unwrap());
+    }
+    return pull::run(
+      m.value_of("SRC").unwrap(),
+      m.value_of("DST").unwrap(),
+      m.is_present("recursive"),
+      m.is_present("preserve"),
+      m.is_present("follow-symlinks"),
+      jobs(m),
+      compression(m),
+    );
+  }
+
+  if let Some(m) = matches.subcommand_matches("sync") {
+    let options = SyncDirOptions {
+      delete: m.is_present("delete"),
+      checksum: m.is_present("checksum"),
+      follow_symlinks: m.is_present("follow-symlinks"),
+      verify: m.is_present("verify"),
+      compression: compression(m),
+    };
+    return sync::run(
+      m.value_of("SRC").unwrap(),
+      m.value_of("DST").unwrap(),
+      &options,
+      m.is_present("dry-run"),
+    );
+  }
+}
+
+fn jobs(m: &ArgMatches) -> usize {
+  m.value_of("jobs")
+    .map(|jobs| jobs.parse().expect("invalid number of jobs"))
+    .unwrap_or(1)
+}
+
+fn compression(m: &ArgMatches) -> Compression {
+  match m.value_of("compression") {
+    Some("any") => Compression::Any,
+    Some("brotli") => Compression::Brotli,
+    Some("lz4") => Compression::Lz4,
+    Some("zstd") => Compression::Zstd,
+    _ => Compression::None,
   }
 }
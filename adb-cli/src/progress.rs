@@ -0,0 +1,158 @@
+use adb_rs::progress::TransferObserver;
+use adb_rs::result::AdbResult;
+use adb_rs::AdbConnection;
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const BAR_WIDTH: usize = 24;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Renders a progress bar with throughput on stderr, one line per file, or
+/// a single line for the whole transfer if files are transferred
+/// concurrently.
+struct ProgressBar {
+  state: Mutex<State>,
+  total_only: bool,
+}
+
+struct State {
+  started: Instant,
+  last_draw: Option<Instant>,
+  // the total line is drawn and needs ending
+  total_started: Option<Instant>,
+}
+
+/// Shows transfer progress if stderr is a terminal, as a single line if up
+/// to `jobs` files are transferred at once.
+pub fn attach(conn: &mut AdbConnection, jobs: usize) {
+  if io::stderr().is_terminal() {
+    let total_only = jobs > 1;
+    conn.set_transfer_observer(Some(Arc::new(ProgressBar {
+      state: Mutex::new(State {
+        started: Instant::now(),
+        last_draw: None,
+        total_started: None,
+      }),
+      total_only,
+    })));
+  }
+}
+
+/// Stops showing progress, ending the total line if any.
+pub fn detach(conn: &mut AdbConnection) {
+  conn.set_transfer_observer(None);
+}
+
+impl Drop for ProgressBar {
+  fn drop(&mut self) {
+    if self.state.lock().unwrap().total_started.is_some() {
+      eprintln!();
+    }
+  }
+}
+
+impl TransferObserver for ProgressBar {
+  fn started(&self, remote_path: &str, total: Option<u64>) {
+    if self.total_only {
+      return;
+    }
+    let mut state = self.state.lock().unwrap();
+    state.started = Instant::now();
+    state.last_draw = None;
+    draw(remote_path, 0, total, Duration::from_secs(0));
+  }
+
+  fn progress(&self, remote_path: &str, transferred: u64, total: Option<u64>) {
+    if self.total_only {
+      return;
+    }
+    let mut state = self.state.lock().unwrap();
+    let now = Instant::now();
+    if let Some(last_draw) = state.last_draw {
+      if now - last_draw < REDRAW_INTERVAL {
+        return;
+      }
+    }
+    state.last_draw = Some(now);
+    draw(remote_path, transferred, total, now - state.started);
+  }
+
+  fn finished(&self, remote_path: &str, result: &AdbResult<u64>) {
+    if self.total_only {
+      return;
+    }
+    let state = self.state.lock().unwrap();
+    if let Ok(n) = *result {
+      draw(remote_path, n, Some(n), state.started.elapsed());
+    }
+    eprintln!();
+  }
+
+  fn total_progress(&self, transferred: u64, total: Option<u64>) {
+    if !self.total_only {
+      return;
+    }
+    let mut state = self.state.lock().unwrap();
+    let now = Instant::now();
+    let started = *state.total_started.get_or_insert(now);
+    if let Some(last_draw) = state.last_draw {
+      if now - last_draw < REDRAW_INTERVAL && Some(transferred) != total {
+        return;
+      }
+    }
+    state.last_draw = Some(now);
+    draw("total", transferred, total, now - started);
+  }
+}
+
+fn draw(name: &str, transferred: u64, total: Option<u64>, elapsed: Duration) {
+  let secs = elapsed.as_secs_f64();
+  let rate = if secs > 0.0 {
+    transferred as f64 / secs
+  } else {
+    0.0
+  };
+
+  let mut line = format!("\r{:<32.32}", name);
+  if let Some(total) = total {
+    // files can grow while they are transferred, past the size known upfront
+    let ratio = if total > 0 {
+      (transferred as f64 / total as f64).min(1.0)
+    } else {
+      1.0
+    };
+    let filled = (ratio * BAR_WIDTH as f64) as usize;
+    line.push_str(&format!(
+      " [{}{}] {:>3}%",
+      "#".repeat(filled),
+      "-".repeat(BAR_WIDTH - filled),
+      (ratio * 100.0) as u32
+    ));
+  }
+  line.push_str(&format!(
+    " {:>10} {:>10}/s",
+    format_size(transferred as f64),
+    format_size(rate)
+  ));
+
+  let stderr = io::stderr();
+  let mut stderr = stderr.lock();
+  stderr.write_all(line.as_bytes()).ok();
+  stderr.flush().ok();
+}
+
+fn format_size(bytes: f64) -> String {
+  const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+  let mut value = bytes;
+  let mut unit = 0;
+  while value >= 1024.0 && unit < UNITS.len() - 1 {
+    value /= 1024.0;
+    unit += 1;
+  }
+  if unit == 0 {
+    format!("{} {}", value as u64, UNITS[unit])
+  } else {
+    format!("{:.1} {}", value, UNITS[unit])
+  }
+}
@@ -0,0 +1,96 @@
+use adb_rs::compression::Compression;
+use adb_rs::glob::is_glob;
+use adb_rs::pull::{AdbPull, PullDirSummary, PullOptions};
+use adb_rs::stat::AdbStat;
+use adb_rs::{AdbClient, AdbConnection};
+use std::path::{Path, PathBuf};
+
+use crate::progress;
+
+pub fn run(
+  remote_path: &str,
+  dst: &str,
+  recursive: bool,
+  preserve: bool,
+  follow_symlinks: bool,
+  jobs: usize,
+  compression: Compression,
+) {
+  let mut conn = AdbClient::new("host::").connect("127.0.0.1:5555").unwrap();
+  progress::attach(&mut conn, jobs);
+
+  let options = PullOptions {
+    preserve_mtime: preserve,
+    preserve_mode: preserve,
+    follow_symlinks,
+    compression,
+    concurrency: jobs,
+  };
+
+  // matches are pulled into `dst` as a directory
+  if is_pattern(&mut conn, remote_path) {
+    let summary = conn.pull_glob(remote_path, dst, &options).unwrap();
+    report(&mut conn, summary);
+    return;
+  }
+
+  let local_path = local_path(remote_path, dst);
+  if recursive {
+    let summary = conn.pull_dir(remote_path, local_path, &options).unwrap();
+    report(&mut conn, summary);
+  } else {
+    conn.pull(remote_path, local_path, &options).unwrap();
+  }
+}
+
+pub fn resume(remote_path: &str, dst: &str) {
+  let mut conn = AdbClient::new("host::").connect("127.0.0.1:5555").unwrap();
+  if is_pattern(&mut conn, remote_path) {
+    eprintln!("--resume needs a single remote file, not a pattern");
+    ::std::process::exit(1);
+  }
+  progress::attach(&mut conn, 1);
+
+  let local_path = local_path(remote_path, dst);
+  let fetched = conn.pull_resume(remote_path, local_path).unwrap();
+  progress::detach(&mut conn);
+  println!("{} bytes fetched, file verified", fetched);
+}
+
+/// Whether `remote_path` has wildcards and does not exist as written, a
+/// path that exists being pulled as named.
+fn is_pattern(conn: &mut AdbConnection, remote_path: &str) -> bool {
+  is_glob(remote_path) && !conn.stat(remote_path).unwrap().exists()
+}
+
+/// `dst`, or the remote file name within it if it is a directory.
+fn local_path(remote_path: &str, dst: &str) -> PathBuf {
+  let mut local_path = Path::new(dst).to_path_buf();
+  if local_path.is_dir() {
+    if let Some(name) = Path::new(remote_path.trim_end_matches('/')).file_name() {
+      local_path.push(name);
+    }
+  }
+  local_path
+}
+
+fn report(conn: &mut AdbConnection, summary: PullDirSummary) {
+  progress::detach(conn);
+  for file in summary.failed() {
+    if let Err(ref err) = file.result {
+      eprintln!("{}: {}", file.remote_path, err);
+    }
+  }
+  for dir in &summary.failed_dirs {
+    eprintln!("{}: {}", dir.remote_path, dir.error);
+  }
+  println!(
+    "{} files pulled, {} failed, {} bytes",
+    summary.total_files() - summary.failed().count(),
+    summary.failed().count(),
+    summary.total_bytes()
+  );
+  if !summary.is_success() {
+    ::std::process::exit(1);
+  }
+}
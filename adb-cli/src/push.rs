@@ -1,8 +1,63 @@
-use adb_rs::push::AdbPush;
-use adb_rs::AdbClient;
+use adb_rs::compression::Compression;
+use adb_rs::glob::is_glob;
+use adb_rs::push::{AdbPush, PushDirSummary, PushOptions};
+use adb_rs::{AdbClient, AdbConnection};
+use std::fs;
+use std::io;
+use std::path::Path;
 
-pub fn run(src: &str, remote_path: &str) {
+use crate::progress;
+
+pub fn run(
+  src: &str,
+  remote_path: &str,
+  follow_symlinks: bool,
+  verify: bool,
+  jobs: usize,
+  compression: Compression,
+) {
   let mut conn = AdbClient::new("host::").connect("127.0.0.1:5555").unwrap();
+  progress::attach(&mut conn, jobs);
+
+  let options = PushOptions {
+    follow_symlinks,
+    verify,
+    compression,
+    concurrency: jobs,
+    ..Default::default()
+  };
+
+  if src == "-" {
+    let stdin = io::stdin();
+    conn
+      .push_reader(stdin.lock(), remote_path, &options)
+      .unwrap();
+  } else if is_glob(src) && fs::symlink_metadata(src).is_err() {
+    // a path that exists is pushed as named, wildcards or not
+    let summary = conn.push_glob(src, remote_path, &options).unwrap();
+    report(&mut conn, summary);
+  } else if Path::new(src).is_dir() {
+    let summary = conn.push_dir(src, remote_path, &options).unwrap();
+    report(&mut conn, summary);
+  } else {
+    conn.push(src, remote_path, &options).unwrap();
+  }
+}
 
-  conn.push(src, remote_path).unwrap();
+fn report(conn: &mut AdbConnection, summary: PushDirSummary) {
+  progress::detach(conn);
+  for file in summary.failed() {
+    if let Err(ref err) = file.result {
+      eprintln!("{}: {}", file.local_path.display(), err);
+    }
+  }
+  println!(
+    "{} files pushed, {} failed, {} bytes",
+    summary.total_files() - summary.failed().count(),
+    summary.failed().count(),
+    summary.total_bytes()
+  );
+  if !summary.is_success() {
+    ::std::process::exit(1);
+  }
 }
@@ -6,5 +6,11 @@ pub fn run(cmd: &str) {
 
   let mut conn = AdbClient::new("host::").connect("127.0.0.1:5555").unwrap();
 
-  stdout().write_all(&conn.shell_exec(cmd).unwrap()).unwrap();
+  let mut stream = conn.shell_stream(cmd).unwrap();
+  let stdout = stdout();
+  let mut stdout = stdout.lock();
+  while let Some(chunk) = stream.recv_chunk().unwrap() {
+    stdout.write_all(&chunk).unwrap();
+    stdout.flush().unwrap();
+  }
 }
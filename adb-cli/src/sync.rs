@@ -0,0 +1,40 @@
+use adb_rs::sync_dir::{AdbSyncDir, SyncDirOptions};
+use adb_rs::AdbClient;
+
+use crate::progress;
+
+pub fn run(src: &str, remote_dir: &str, options: &SyncDirOptions, dry_run: bool) {
+  let mut conn = AdbClient::new("host::").connect("127.0.0.1:5555").unwrap();
+
+  if dry_run {
+    let plan = conn.plan_sync_dir(src, remote_dir, options).unwrap();
+    for action in &plan.actions {
+      println!("{}", action);
+    }
+    println!(
+      "{} actions planned, {} files up to date",
+      plan.actions.len(),
+      plan.unchanged
+    );
+    return;
+  }
+
+  progress::attach(&mut conn, 1);
+
+  let summary = conn.sync_dir(src, remote_dir, options).unwrap();
+  for action in summary.failed() {
+    if let Err(ref err) = action.result {
+      eprintln!("{}: {}", action.action, err);
+    }
+  }
+  println!(
+    "{} actions done, {} failed, {} files up to date, {} bytes",
+    summary.actions.len() - summary.failed().count(),
+    summary.failed().count(),
+    summary.unchanged,
+    summary.total_bytes()
+  );
+  if !summary.is_success() {
+    ::std::process::exit(1);
+  }
+}
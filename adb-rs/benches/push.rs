@@ -0,0 +1,180 @@
+//! Measures `push_reader` throughput against an in-process mock device.
+//!
+//! The mock delays every `A_OKAY` to emulate link latency, and the pushed
+//! reader sleeps per MiB to emulate disk reads, so the numbers show how well
+//! reading overlaps sending. Run with `cargo bench -p adb-rs --bench push`.
+
+use adb_rs::push::{AdbPush, PushOptions};
+use adb_rs::AdbClient;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const A_CNXN: u32 = 0x4e584e43;
+const A_OPEN: u32 = 0x4e45504f;
+const A_OKAY: u32 = 0x59414b4f;
+const A_CLSE: u32 = 0x45534c43;
+const A_WRTE: u32 = 0x45545257;
+
+const MAX_DATA: u32 = 1024 * 1024;
+const PUSH_SIZE: u64 = 64 * 1024 * 1024;
+const MIB: f64 = 1024.0 * 1024.0;
+
+fn main() {
+  for &(latency_ms, disk_ms) in &[(0, 0), (0, 4), (4, 0), (4, 4)] {
+    let latency = Duration::from_millis(latency_ms);
+    let disk = Duration::from_millis(disk_ms);
+    let addr = spawn_device(latency);
+
+    let mut conn = AdbClient::new("host::").connect(addr).unwrap();
+    let r = SlowReader {
+      inner: io::repeat(0x5a).take(PUSH_SIZE),
+      delay_per_mib: disk,
+      pending: 0,
+    };
+
+    let start = Instant::now();
+    conn
+      .push_reader(r, "/data/local/tmp/bench", &PushOptions::default())
+      .unwrap();
+    let elapsed = start.elapsed();
+
+    println!(
+      "push {} MiB, latency {:>2} ms/packet, disk {:>2} ms/MiB: {:>8.1} MiB/s",
+      PUSH_SIZE / 1024 / 1024,
+      latency_ms,
+      disk_ms,
+      PUSH_SIZE as f64 / MIB / elapsed.as_secs_f64()
+    );
+  }
+}
+
+/// Sleeps `delay_per_mib` after every MiB read.
+struct SlowReader<R> {
+  inner: R,
+  delay_per_mib: Duration,
+  pending: usize,
+}
+
+impl<R: Read> Read for SlowReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let n = self.inner.read(buf)?;
+    self.pending += n;
+    while self.pending >= 1024 * 1024 {
+      self.pending -= 1024 * 1024;
+      thread::sleep(self.delay_per_mib);
+    }
+    Ok(n)
+  }
+}
+
+fn spawn_device(latency: Duration) -> String {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let addr = listener.local_addr().unwrap().to_string();
+  thread::spawn(move || {
+    for stream in listener.incoming() {
+      let stream = stream.unwrap();
+      thread::spawn(move || serve(stream, latency).ok());
+    }
+  });
+  addr
+}
+
+fn read_packet(s: &mut TcpStream) -> io::Result<(u32, u32, u32, Vec<u8>)> {
+  let mut header = [0; 24];
+  s.read_exact(&mut header)?;
+  let word =
+    |i: usize| u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+  let mut payload = vec![0; word(12) as usize];
+  s.read_exact(&mut payload)?;
+  Ok((word(0), word(4), word(8), payload))
+}
+
+fn write_packet(
+  s: &mut TcpStream,
+  cmd: u32,
+  arg0: u32,
+  arg1: u32,
+  payload: &[u8],
+) -> io::Result<()> {
+  let crc = payload
+    .iter()
+    .fold(0u32, |acc, &b| acc.wrapping_add(b as u32));
+  let mut buf = Vec::with_capacity(24 + payload.len());
+  for word in &[
+    cmd,
+    arg0,
+    arg1,
+    payload.len() as u32,
+    crc,
+    cmd ^ 0xffff_ffff,
+  ] {
+    buf.extend_from_slice(&word.to_le_bytes());
+  }
+  buf.extend_from_slice(payload);
+  s.write_all(&buf)
+}
+
+fn serve(mut s: TcpStream, latency: Duration) -> io::Result<()> {
+  s.set_nodelay(true)?;
+  const LOCAL_ID: u32 = 1;
+  let mut remote_id = 0;
+  let mut sync = SyncParser::default();
+
+  loop {
+    let (cmd, arg0, _, payload) = read_packet(&mut s)?;
+    match cmd {
+      A_CNXN => write_packet(&mut s, A_CNXN, 0x0100_0001, MAX_DATA, b"device::features=")?,
+      A_OPEN => {
+        remote_id = arg0;
+        write_packet(&mut s, A_OKAY, LOCAL_ID, remote_id, &[])?;
+      }
+      A_WRTE => {
+        thread::sleep(latency);
+        write_packet(&mut s, A_OKAY, LOCAL_ID, remote_id, &[])?;
+        if let Some(reply) = sync.feed(&payload) {
+          write_packet(&mut s, A_WRTE, LOCAL_ID, remote_id, &reply)?;
+        }
+      }
+      A_CLSE => return write_packet(&mut s, A_CLSE, LOCAL_ID, remote_id, &[]),
+      _ => {}
+    }
+  }
+}
+
+/// Consumes sync requests, replying to `STAT` and to a completed `SEND`.
+#[derive(Default)]
+struct SyncParser {
+  buf: Vec<u8>,
+  sending: bool,
+}
+
+impl SyncParser {
+  fn feed(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+    self.buf.extend_from_slice(data);
+    let mut reply = None;
+    while self.buf.len() >= 8 {
+      let id = &self.buf[0..4];
+      let len = u32::from_le_bytes([self.buf[4], self.buf[5], self.buf[6], self.buf[7]]) as usize;
+      let record_len = match id {
+        b"DONE" | b"QUIT" => 8,
+        _ => 8 + len,
+      };
+      if self.buf.len() < record_len {
+        break;
+      }
+      match id {
+        b"STAT" => reply = Some(b"STAT\0\0\0\0\0\0\0\0\0\0\0\0".to_vec()),
+        b"SEND" => self.sending = true,
+        b"DONE" if self.sending => {
+          self.sending = false;
+          reply = Some(b"OKAY\0\0\0\0".to_vec());
+        }
+        _ => {}
+      }
+      self.buf.drain(..record_len);
+    }
+    reply
+  }
+}
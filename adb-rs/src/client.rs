@@ -1,14 +1,17 @@
 use bytes::buf::FromBuf;
 use bytes::{Bytes, BytesMut};
-use crossbeam_channel::{bounded, select, unbounded, Receiver, Sender};
+use crossbeam_channel::{after, bounded, never, select, unbounded, Receiver, Sender};
 use std::collections::HashMap;
 use std::io::prelude::*;
 use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread::{self, JoinHandle};
+use std::time::Instant;
 
 pub use crate::message::Command;
 use crate::message::{Connect, Header};
+use crate::progress::TransferObserver;
 use crate::result::*;
 
 #[derive(Debug)]
@@ -76,12 +79,14 @@ impl AdbClient {
                 .map(move |_| ConnectionPacket {
                   header,
                   payload: payload.freeze(),
+                  cancel: false,
                 })
                 .map_err(Into::into)
             } else {
               Ok(ConnectionPacket {
                 header,
                 payload: payload.freeze(),
+                cancel: false,
               })
             }
           })
@@ -111,20 +116,34 @@ impl AdbClient {
           match packet {
             Ok(packet) => {
               let local_id = packet.header.arg0;
-              let locked = streams.read().unwrap();
-              if let Some(ctx) = locked.get(&local_id) {
-                let write = packet
+              let write = |stream: &mut TcpStream| {
+                packet
                   .header
-                  .encode(&mut stream)
-                  .and_then(|_| stream.write_all(&packet.payload).map_err(Into::into));
-                match write {
+                  .encode(stream)
+                  .and_then(|_| stream.write_all(&packet.payload).map_err(Into::into))
+              };
+              if packet.cancel {
+                // nobody waits for the result of a `CancelHandle`'s close
+                if let Err(err) = write(&mut stream) {
+                  conn_dead = true;
+                  error_s.send(err).ok();
+                }
+                closed_local_ids.push(local_id);
+              } else if let Some(write_result_s) = streams
+                .read()
+                .unwrap()
+                .get(&local_id)
+                .map(|ctx| ctx.write_result_s.clone())
+              {
+                // not holding the lock while blocked, see the dispatch worker
+                match write(&mut stream) {
                   Ok(_) => {
-                    if let Err(_) = ctx.write_result_s.send(Ok(())) {
+                    if let Err(_) = write_result_s.send(Ok(())) {
                       closed_local_ids.push(local_id);
                     }
                   }
                   Err(err) => {
-                    if let Err(_) = ctx.write_result_s.send(Err(AdbError::Disconnected)) {
+                    if let Err(_) = write_result_s.send(Err(AdbError::Disconnected)) {
                       closed_local_ids.push(local_id);
                     }
                     conn_dead = true;
@@ -169,12 +188,26 @@ impl AdbClient {
               match packet {
                 Ok(packet) => {
                   let local_id = packet.header.arg1;
-                  let locked = streams.read().unwrap();
-                  match locked.get(&packet.header.arg1) {
-                    Some(ctx) => {
+                  // the send blocks until the stream is read; holding the lock
+                  // meanwhile would block `open_stream` and, behind it, the
+                  // writer worker that stream may be waiting on
+                  let ctx = streams
+                    .read()
+                    .unwrap()
+                    .get(&local_id)
+                    .map(|ctx| (ctx.stream_reader_s.clone(), ctx.cancel_r.clone()));
+                  match ctx {
+                    Some((stream_reader_s, cancel_r)) => {
                       if packet.header.get_command().is_some() {
-                        if let Err(_) = ctx.stream_reader_s.send(packet) {
-                          closed_local_ids.push(local_id);
+                        select! {
+                          send(stream_reader_s, packet) -> res => {
+                            if res.is_err() {
+                              closed_local_ids.push(local_id);
+                            }
+                          },
+                          recv(cancel_r) -> _ => {
+                            debug!("read packet discarded: stream cancelled, local_id = {}", local_id);
+                          },
                         }
                       } else {
                         error!(
@@ -224,16 +257,20 @@ impl AdbClient {
       }
     });
 
+    let device_features = parse_features(&device_id);
+
     Ok(AdbConnection {
       system_identity: self.system_identity,
       device_system_identity: device_id.to_string(),
+      device_features,
       device_version: resp.arg0,
       device_max_data: resp.arg1,
       tcp_stream: stream,
-      local_id_counter: 0,
+      local_id_counter: AtomicU32::new(0),
       workers: vec![reader_worker, writer_worker, dispatch_worker],
       streams,
       conn_writer_s,
+      transfer_observer: None,
     })
   }
 }
@@ -241,6 +278,9 @@ impl AdbClient {
 struct ConnectionPacket {
   header: Header,
   payload: Bytes,
+  // a `CancelHandle`'s close: no write result is sent back, and the stream is
+  // unregistered once it is written
+  cancel: bool,
 }
 
 #[derive(Debug)]
@@ -273,19 +313,22 @@ struct StreamContext {
   remote_id: u32,
   stream_reader_s: Sender<ConnectionPacket>,
   write_result_s: Sender<AdbResult<()>>,
+  cancel_r: Receiver<()>,
 }
 
 #[derive(Debug)]
 pub struct AdbConnection {
   system_identity: String,
   device_system_identity: String,
+  device_features: Vec<String>,
   device_version: u32,
   device_max_data: u32,
   tcp_stream: TcpStream,
-  local_id_counter: u32,
+  local_id_counter: AtomicU32,
   workers: Vec<JoinHandle<()>>,
   streams: Arc<RwLock<HashMap<u32, StreamContext>>>,
   conn_writer_s: Sender<ConnectionPacket>,
+  transfer_observer: Option<Arc<dyn TransferObserver>>,
 }
 
 impl Drop for AdbConnection {
@@ -305,11 +348,30 @@ impl AdbConnection {
     self.device_max_data as usize
   }
 
-  pub fn open_stream(&mut self, destination: &str) -> AdbResult<AdbStream> {
+  /// Features advertised by the device in its `CNXN` banner, e.g. `shell_v2`.
+  pub fn device_features(&self) -> &[String] {
+    &self.device_features
+  }
+
+  pub fn has_feature(&self, feature: &str) -> bool {
+    self.device_features.iter().any(|f| f == feature)
+  }
+
+  /// Sets the observer notified of the progress of sync file transfers.
+  pub fn set_transfer_observer(&mut self, observer: Option<Arc<dyn TransferObserver>>) {
+    self.transfer_observer = observer;
+  }
+
+  pub fn transfer_observer(&self) -> Option<&Arc<dyn TransferObserver>> {
+    self.transfer_observer.as_ref()
+  }
+
+  /// Opens a stream to `destination`. Streams are multiplexed over the
+  /// connection and may be opened and used from several threads at once.
+  pub fn open_stream(&self, destination: &str) -> AdbResult<AdbStream> {
     use bytes::BufMut;
 
-    self.local_id_counter = self.local_id_counter + 1;
-    let local_id = self.local_id_counter;
+    let local_id = self.local_id_counter.fetch_add(1, Ordering::SeqCst) + 1;
     debug!(
       "opening stream: local_id = {}, destination = {}...",
       local_id, destination
@@ -317,12 +379,15 @@ impl AdbConnection {
 
     let (write_result_s, write_result_r) = bounded::<AdbResult<()>>(1);
     let (stream_reader_s, stream_reader_r) = bounded::<ConnectionPacket>(1);
+    // nothing is ever sent: cancelling drops the sender, waking every receiver
+    let (cancel_s, cancel_r) = bounded::<()>(0);
 
     let ctx = StreamContext {
       local_id,
       remote_id: 0,
       stream_reader_s,
       write_result_s,
+      cancel_r: cancel_r.clone(),
     };
 
     self.streams.write().unwrap().insert(local_id, ctx);
@@ -339,6 +404,7 @@ impl AdbConnection {
         .data(&dst_bytes)
         .finalize(),
       payload: dst_bytes,
+      cancel: false,
     };
 
     self
@@ -362,49 +428,156 @@ impl AdbConnection {
     Ok(AdbStream {
       local_id,
       remote_id,
+      max_data: self.device_max_data as usize,
       stream_reader: stream_reader_r,
       writer: self.conn_writer_s.clone(),
       write_result_r,
+      cancel: CancelHandle {
+        local_id,
+        remote_id,
+        writer: self.conn_writer_s.clone(),
+        cancelled: Arc::new(AtomicBool::new(false)),
+        cancel_s: Arc::new(Mutex::new(Some(cancel_s))),
+      },
+      cancel_r,
     })
   }
 }
 
+// banner format: "<systemtype>:<serialno>:<key>=<value>;...;features=<f1>,<f2>,..."
+fn parse_features(device_id: &str) -> Vec<String> {
+  device_id
+    .splitn(3, ':')
+    .nth(2)
+    .and_then(|props| {
+      props
+        .split(';')
+        .find(|prop| prop.starts_with("features="))
+        .map(|prop| {
+          prop["features=".len()..]
+            .split(',')
+            .filter(|f| !f.is_empty())
+            .map(|f| f.trim_end_matches('\0').to_string())
+            .collect()
+        })
+    })
+    .unwrap_or_default()
+}
+
+/// Closes an `AdbStream` from another thread, e.g. to enforce a time budget.
+///
+/// Cancelling sends `A_CLSE` to the device and makes pending and later
+/// operations on the stream fail with `AdbError::Cancelled`, except
+/// `send_close`, which has nothing left to do.
+#[derive(Debug, Clone)]
+pub struct CancelHandle {
+  local_id: u32,
+  remote_id: u32,
+  writer: Sender<ConnectionPacket>,
+  cancelled: Arc<AtomicBool>,
+  cancel_s: Arc<Mutex<Option<Sender<()>>>>,
+}
+
+impl CancelHandle {
+  pub fn cancel(&self) {
+    if self.cancelled.swap(true, Ordering::SeqCst) {
+      return;
+    }
+    self.cancel_s.lock().unwrap().take();
+
+    let header = Header::new(Command::A_CLSE)
+      .arg0(self.local_id)
+      .arg1(self.remote_id)
+      .finalize();
+    self
+      .writer
+      .send(ConnectionPacket {
+        header,
+        payload: Bytes::new(),
+        cancel: true,
+      })
+      .ok();
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::SeqCst)
+  }
+}
+
 #[derive(Debug)]
 pub struct AdbStream {
   local_id: u32,
   remote_id: u32,
+  max_data: usize,
   stream_reader: Receiver<ConnectionPacket>,
   writer: Sender<ConnectionPacket>,
   write_result_r: Receiver<AdbResult<()>>,
+  cancel: CancelHandle,
+  cancel_r: Receiver<()>,
 }
 
 impl AdbStream {
+  pub fn max_data_len(&self) -> usize {
+    self.max_data
+  }
+
+  /// Sends `packet`, waiting until it is written to the connection.
+  ///
+  /// Fails with `AdbError::Cancelled` once the stream's `CancelHandle` fires,
+  /// even while waiting.
   pub fn send(&self, packet: AdbStreamPacket) -> AdbResult<()> {
-    self
-      .writer
-      .send(ConnectionPacket {
-        header: Header::new(packet.command)
-          .arg0(self.local_id)
-          .arg1(self.remote_id)
-          .data(&packet.payload)
-          .finalize(),
-        payload: packet.payload,
-      })
-      .map_err(|_| AdbError::Disconnected)
-      .and_then(|_| {
-        self
-          .write_result_r
-          .recv()
-          .map_err(|_| AdbError::Disconnected)
-          .and_then(|res| res.map(|_| ()))
-      })
+    if self.cancel.is_cancelled() {
+      return Err(AdbError::Cancelled);
+    }
+
+    let packet = ConnectionPacket {
+      header: Header::new(packet.command)
+        .arg0(self.local_id)
+        .arg1(self.remote_id)
+        .data(&packet.payload)
+        .finalize(),
+      payload: packet.payload,
+      cancel: false,
+    };
+
+    // once cancelled, the result of a packet already handed to the writer is
+    // left in the channel, never to be mistaken for a later one's
+    select! {
+      send(self.writer, packet) -> res => res.map_err(|_| AdbError::Disconnected)?,
+      recv(self.cancel_r) -> _ => return Err(AdbError::Cancelled),
+    }
+    select! {
+      recv(self.write_result_r) -> res => res.map_err(|_| AdbError::Disconnected)?,
+      recv(self.cancel_r) -> _ => Err(AdbError::Cancelled),
+    }
+  }
+
+  pub fn cancel_handle(&self) -> CancelHandle {
+    self.cancel.clone()
   }
 
   pub fn recv(&self) -> AdbResult<AdbStreamPacket> {
-    let packet = self
-      .stream_reader
-      .recv()
-      .map_err(|_| AdbError::Disconnected)?;
+    self.recv_deadline(None)
+  }
+
+  /// Receives the next packet, failing with `AdbError::Timeout` once
+  /// `deadline` has passed or `AdbError::Cancelled` if the stream's
+  /// `CancelHandle` fires first.
+  pub fn recv_deadline(&self, deadline: Option<Instant>) -> AdbResult<AdbStreamPacket> {
+    if self.cancel.is_cancelled() {
+      return Err(AdbError::Cancelled);
+    }
+
+    let timeout = match deadline {
+      Some(deadline) => after(deadline.saturating_duration_since(Instant::now())),
+      None => never(),
+    };
+
+    let packet = select! {
+      recv(self.stream_reader) -> packet => packet.map_err(|_| AdbError::Disconnected)?,
+      recv(self.cancel_r) -> _ => return Err(AdbError::Cancelled),
+      recv(timeout) -> _ => return Err(AdbError::Timeout),
+    };
 
     Ok(AdbStreamPacket {
       command: packet
@@ -445,10 +618,108 @@ impl AdbStream {
     Ok(packet)
   }
 
+  /// Closes the stream, unless its `CancelHandle` already did.
   pub fn send_close(&self) -> AdbResult<()> {
+    if self.cancel.is_cancelled() {
+      return Ok(());
+    }
     self.send(AdbStreamPacket {
       command: Command::A_CLSE,
       payload: Bytes::new(),
     })
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::thread;
+
+  const LOCAL_ID: u32 = 1;
+  const REMOTE_ID: u32 = 2;
+
+  // the connection side of a stream: the writer and dispatch workers
+  struct MockConnection {
+    writer_r: Receiver<ConnectionPacket>,
+    write_result_s: Sender<AdbResult<()>>,
+    _stream_reader_s: Sender<ConnectionPacket>,
+  }
+
+  fn mock_stream() -> (AdbStream, MockConnection) {
+    let (writer_s, writer_r) = bounded(0);
+    let (write_result_s, write_result_r) = bounded(1);
+    let (stream_reader_s, stream_reader_r) = bounded(1);
+    let (cancel_s, cancel_r) = bounded(0);
+    let stream = AdbStream {
+      local_id: LOCAL_ID,
+      remote_id: REMOTE_ID,
+      max_data: 4096,
+      stream_reader: stream_reader_r,
+      writer: writer_s.clone(),
+      write_result_r,
+      cancel: CancelHandle {
+        local_id: LOCAL_ID,
+        remote_id: REMOTE_ID,
+        writer: writer_s,
+        cancelled: Arc::new(AtomicBool::new(false)),
+        cancel_s: Arc::new(Mutex::new(Some(cancel_s))),
+      },
+      cancel_r,
+    };
+    let conn = MockConnection {
+      writer_r,
+      write_result_s,
+      _stream_reader_s: stream_reader_s,
+    };
+    (stream, conn)
+  }
+
+  fn assert_cancel_close(packet: ConnectionPacket) {
+    assert_eq!(packet.header.get_command(), Some(Command::A_CLSE));
+    assert_eq!(packet.header.arg0, LOCAL_ID);
+    assert_eq!(packet.header.arg1, REMOTE_ID);
+    assert!(packet.cancel);
+  }
+
+  #[test]
+  fn cancel_ends_blocked_send() {
+    let (stream, conn) = mock_stream();
+    let cancel = stream.cancel_handle();
+
+    thread::scope(|scope| {
+      let sender = scope.spawn(|| stream.send(AdbStreamPacket::new_write(b"data")));
+      // the write is taken but never completes
+      let packet = conn.writer_r.recv().unwrap();
+      assert_eq!(packet.header.get_command(), Some(Command::A_WRTE));
+
+      scope.spawn(|| cancel.cancel());
+      assert_cancel_close(conn.writer_r.recv().unwrap());
+      assert!(matches!(sender.join().unwrap(), Err(AdbError::Cancelled)));
+    });
+
+    // the stale result is not taken for a later send, and nothing more is
+    // written, not even a second close
+    conn.write_result_s.send(Ok(())).unwrap();
+    assert!(matches!(
+      stream.send(AdbStreamPacket::new_write(b"more")),
+      Err(AdbError::Cancelled)
+    ));
+    assert!(stream.send_close().is_ok());
+    cancel.cancel();
+    assert!(conn.writer_r.try_recv().is_err());
+  }
+
+  #[test]
+  fn cancel_ends_blocked_recv() {
+    let (stream, conn) = mock_stream();
+    let cancel = stream.cancel_handle();
+
+    thread::scope(|scope| {
+      let receiver = scope.spawn(|| stream.recv());
+      scope.spawn(|| cancel.cancel());
+      assert_cancel_close(conn.writer_r.recv().unwrap());
+      assert!(matches!(receiver.join().unwrap(), Err(AdbError::Cancelled)));
+    });
+    assert!(matches!(stream.recv(), Err(AdbError::Cancelled)));
+  }
+}
@@ -0,0 +1,329 @@
+use std::io::Read;
+#[cfg(any(feature = "brotli", feature = "lz4", feature = "zstd"))]
+use std::io::{self, Write};
+
+use crate::result::*;
+
+/// Compression of sync file transfers, using `SND2`/`RCV2` on devices
+/// advertising `sendrecv_v2`.
+///
+/// Each codec needs the device to advertise it and the cargo feature of the
+/// same name (`brotli`, `lz4` or `zstd`) to be enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+  /// Transfers are not compressed.
+  #[default]
+  None,
+  /// Uses a codec supported by both sides if there is one, otherwise none.
+  Any,
+  Brotli,
+  Lz4,
+  Zstd,
+}
+
+/// A codec both sides support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Codec {
+  Brotli,
+  Lz4,
+  Zstd,
+}
+
+impl Codec {
+  /// The `sync_send_v2`/`sync_recv_v2` flag selecting the codec.
+  pub(crate) fn flag(self) -> u32 {
+    match self {
+      Codec::Brotli => 1,
+      Codec::Lz4 => 2,
+      Codec::Zstd => 4,
+    }
+  }
+
+  fn feature(self) -> &'static str {
+    match self {
+      Codec::Brotli => "sendrecv_v2_brotli",
+      Codec::Lz4 => "sendrecv_v2_lz4",
+      Codec::Zstd => "sendrecv_v2_zstd",
+    }
+  }
+
+  fn is_compiled(self) -> bool {
+    match self {
+      Codec::Brotli => cfg!(feature = "brotli"),
+      Codec::Lz4 => cfg!(feature = "lz4"),
+      Codec::Zstd => cfg!(feature = "zstd"),
+    }
+  }
+
+  fn is_supported(self, features: &[String]) -> bool {
+    self.is_compiled()
+      && features.iter().any(|f| f == "sendrecv_v2")
+      && features.iter().any(|f| f == self.feature())
+  }
+}
+
+impl Compression {
+  /// Picks the codec to use with a device advertising `features`.
+  ///
+  /// `Any` prefers the fastest codec. Requesting a specific codec that
+  /// either side lacks is an error.
+  pub(crate) fn resolve(self, features: &[String]) -> AdbResult<Option<Codec>> {
+    let codec = match self {
+      Compression::None => return Ok(None),
+      Compression::Any => {
+        return Ok(
+          [Codec::Lz4, Codec::Zstd, Codec::Brotli]
+            .iter()
+            .cloned()
+            .find(|codec| codec.is_supported(features)),
+        )
+      }
+      Compression::Brotli => Codec::Brotli,
+      Compression::Lz4 => Codec::Lz4,
+      Compression::Zstd => Codec::Zstd,
+    };
+
+    if !codec.is_compiled() {
+      return Err(not_compiled(codec));
+    }
+    if !codec.is_supported(features) {
+      return Err(AdbError::Fail(format!(
+        "{:?} compression is not supported by the device",
+        codec
+      )));
+    }
+    Ok(Some(codec))
+  }
+}
+
+/// Wraps `r` so that reading yields its contents compressed with `codec`.
+pub(crate) fn encoder<'a, R: Read + 'a>(codec: Codec, r: R) -> AdbResult<Box<dyn Read + 'a>> {
+  match codec {
+    #[cfg(feature = "brotli")]
+    Codec::Brotli => Ok(Box::new(EncodeReader::new(
+      r,
+      brotli::CompressorWriter::new(vec![], 4096, 5, 22),
+    ))),
+    #[cfg(feature = "lz4")]
+    Codec::Lz4 => Ok(Box::new(EncodeReader::new(
+      r,
+      lz4_flex::frame::FrameEncoder::new(vec![]),
+    ))),
+    #[cfg(feature = "zstd")]
+    Codec::Zstd => Ok(Box::new(EncodeReader::new(
+      r,
+      zstd::stream::write::Encoder::new(vec![], 0)?,
+    ))),
+    #[allow(unreachable_patterns)]
+    _ => {
+      drop(r);
+      Err(not_compiled(codec))
+    }
+  }
+}
+
+/// Wraps `r`, a stream compressed with `codec`, so that reading yields the
+/// decompressed contents.
+pub(crate) fn decoder<'a, R: Read + 'a>(codec: Codec, r: R) -> AdbResult<Box<dyn Read + 'a>> {
+  match codec {
+    #[cfg(feature = "brotli")]
+    Codec::Brotli => Ok(Box::new(brotli::Decompressor::new(r, 4096))),
+    #[cfg(feature = "lz4")]
+    Codec::Lz4 => Ok(Box::new(lz4_flex::frame::FrameDecoder::new(r))),
+    #[cfg(feature = "zstd")]
+    Codec::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(r)?)),
+    #[allow(unreachable_patterns)]
+    _ => {
+      drop(r);
+      Err(not_compiled(codec))
+    }
+  }
+}
+
+fn not_compiled(codec: Codec) -> AdbError {
+  AdbError::Fail(format!(
+    "{:?} compression is not enabled in this build",
+    codec
+  ))
+}
+
+/// A streaming compressor writing into a buffer.
+#[cfg(any(feature = "brotli", feature = "lz4", feature = "zstd"))]
+trait Encode: Write {
+  /// The compressed bytes produced so far.
+  fn output(&mut self) -> &mut Vec<u8>;
+  /// Ends the stream, returning the remaining compressed bytes.
+  fn finish(self) -> io::Result<Vec<u8>>;
+}
+
+#[cfg(feature = "brotli")]
+impl Encode for brotli::CompressorWriter<Vec<u8>> {
+  fn output(&mut self) -> &mut Vec<u8> {
+    self.get_mut()
+  }
+
+  fn finish(self) -> io::Result<Vec<u8>> {
+    Ok(self.into_inner())
+  }
+}
+
+#[cfg(feature = "lz4")]
+impl Encode for lz4_flex::frame::FrameEncoder<Vec<u8>> {
+  fn output(&mut self) -> &mut Vec<u8> {
+    self.get_mut()
+  }
+
+  fn finish(self) -> io::Result<Vec<u8>> {
+    lz4_flex::frame::FrameEncoder::finish(self).map_err(io::Error::other)
+  }
+}
+
+#[cfg(feature = "zstd")]
+impl Encode for zstd::stream::write::Encoder<'static, Vec<u8>> {
+  fn output(&mut self) -> &mut Vec<u8> {
+    self.get_mut()
+  }
+
+  fn finish(self) -> io::Result<Vec<u8>> {
+    zstd::stream::write::Encoder::finish(self)
+  }
+}
+
+/// Adapts a write-side compressor into a reader over the compressed stream.
+#[cfg(any(feature = "brotli", feature = "lz4", feature = "zstd"))]
+struct EncodeReader<R, E> {
+  inner: R,
+  encoder: Option<E>,
+  chunk: Vec<u8>,
+  out: Vec<u8>,
+  pos: usize,
+}
+
+#[cfg(any(feature = "brotli", feature = "lz4", feature = "zstd"))]
+impl<R: Read, E: Encode> EncodeReader<R, E> {
+  fn new(inner: R, encoder: E) -> Self {
+    EncodeReader {
+      inner,
+      encoder: Some(encoder),
+      chunk: vec![0; 64 * 1024],
+      out: vec![],
+      pos: 0,
+    }
+  }
+}
+
+#[cfg(any(feature = "brotli", feature = "lz4", feature = "zstd"))]
+impl<R: Read, E: Encode> Read for EncodeReader<R, E> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    while self.pos == self.out.len() {
+      self.out.clear();
+      self.pos = 0;
+
+      let mut encoder = match self.encoder.take() {
+        Some(encoder) => encoder,
+        None => return Ok(0),
+      };
+
+      let n = self.inner.read(&mut self.chunk)?;
+      if n == 0 {
+        self.out = encoder.finish()?;
+      } else {
+        encoder.write_all(&self.chunk[..n])?;
+        ::std::mem::swap(&mut self.out, encoder.output());
+        self.encoder = Some(encoder);
+      }
+    }
+
+    let n = ::std::cmp::min(buf.len(), self.out.len() - self.pos);
+    buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+    self.pos += n;
+    Ok(n)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[cfg(any(feature = "brotli", feature = "lz4", feature = "zstd"))]
+  fn round_trip(codec: Codec) {
+    // larger than `EncodeReader`'s chunk, read back in small pieces
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+
+    let mut compressed = vec![];
+    encoder(codec, &data[..])
+      .unwrap()
+      .read_to_end(&mut compressed)
+      .unwrap();
+    assert!(compressed.len() < data.len());
+
+    let mut decoded = vec![];
+    let mut r = decoder(codec, &compressed[..]).unwrap();
+    let mut buf = [0; 1000];
+    loop {
+      let n = r.read(&mut buf).unwrap();
+      if n == 0 {
+        break;
+      }
+      decoded.extend_from_slice(&buf[..n]);
+    }
+    assert!(decoded == data);
+  }
+
+  #[cfg(feature = "brotli")]
+  #[test]
+  fn brotli_round_trip() {
+    round_trip(Codec::Brotli);
+  }
+
+  #[cfg(feature = "lz4")]
+  #[test]
+  fn lz4_round_trip() {
+    round_trip(Codec::Lz4);
+  }
+
+  #[cfg(feature = "zstd")]
+  #[test]
+  fn zstd_round_trip() {
+    round_trip(Codec::Zstd);
+  }
+
+  #[test]
+  fn empty_round_trip() {
+    for &codec in &[Codec::Brotli, Codec::Lz4, Codec::Zstd] {
+      if !codec.is_compiled() {
+        assert!(encoder(codec, &b""[..]).is_err());
+        continue;
+      }
+      let mut compressed = vec![];
+      encoder(codec, &b""[..])
+        .unwrap()
+        .read_to_end(&mut compressed)
+        .unwrap();
+      let mut decoded = vec![];
+      decoder(codec, &compressed[..])
+        .unwrap()
+        .read_to_end(&mut decoded)
+        .unwrap();
+      assert!(decoded.is_empty());
+    }
+  }
+
+  #[test]
+  fn resolve_prefers_lz4() {
+    let features: Vec<String> = ["sendrecv_v2", "sendrecv_v2_brotli", "sendrecv_v2_lz4"]
+      .iter()
+      .map(|f| f.to_string())
+      .collect();
+    let expected = if cfg!(feature = "lz4") {
+      Some(Codec::Lz4)
+    } else if cfg!(feature = "brotli") {
+      Some(Codec::Brotli)
+    } else {
+      None
+    };
+    assert_eq!(Compression::Any.resolve(&features).unwrap(), expected);
+    assert_eq!(Compression::None.resolve(&features).unwrap(), None);
+    assert!(Compression::Zstd.resolve(&features).is_err());
+  }
+}
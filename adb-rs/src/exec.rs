@@ -0,0 +1,101 @@
+use std::io::prelude::*;
+
+use super::client::*;
+use super::shell::ShellStream;
+use crate::result::*;
+
+/// Runs commands through the `exec:` service.
+///
+/// Unlike `shell:`, `exec:` never allocates a PTY, so the output is passed
+/// through unmodified. Use it for binary data such as `screencap -p` or `tar`.
+pub trait AdbExec {
+  fn exec(&mut self, cmd: &str) -> AdbResult<Vec<u8>>;
+  fn exec_stream(&mut self, cmd: &str) -> AdbResult<ShellStream>;
+  fn exec_in<R: Read>(&mut self, cmd: &str, r: R) -> AdbResult<()>;
+}
+
+impl AdbExec for AdbConnection {
+  fn exec(&mut self, cmd: &str) -> AdbResult<Vec<u8>> {
+    exec_output(self, cmd)
+  }
+
+  fn exec_stream(&mut self, cmd: &str) -> AdbResult<ShellStream> {
+    let stream = self.open_stream(&format!("exec:{}", cmd))?;
+    Ok(ShellStream::new(stream))
+  }
+
+  fn exec_in<R: Read>(&mut self, cmd: &str, r: R) -> AdbResult<()> {
+    let mut r = r;
+    let mut stream = self.exec_stream(cmd)?;
+    let mut buf = vec![0; stream.max_data_len()];
+
+    loop {
+      let n = r.read(&mut buf)?;
+      if n == 0 {
+        break;
+      }
+      stream.send_stdin(&buf[..n])?;
+    }
+
+    stream.flush()?;
+    stream.close()
+  }
+}
+
+/// `AdbExec::exec` on a shared connection, for transfers running on several
+/// threads.
+pub(crate) fn exec_output(conn: &AdbConnection, cmd: &str) -> AdbResult<Vec<u8>> {
+  let stream = conn.open_stream(&format!("exec:{}", cmd))?;
+  let mut stream = ShellStream::new(stream);
+  let mut buf = vec![];
+
+  while let Some(chunk) = stream.recv_chunk()? {
+    buf.write_all(&chunk)?;
+  }
+
+  Ok(buf)
+}
+
+/// Runs `cmd` through `exec:`, returning its output and exit status.
+///
+/// `exec:` has no exit status, so it is echoed after the output, which works
+/// the same on devices without `shell_v2`. The newline ending the output is
+/// dropped.
+pub(crate) fn exec_status(conn: &AdbConnection, cmd: &str) -> AdbResult<(String, i32)> {
+  let output = exec_output(conn, &format!("{}; echo $?", cmd))?;
+  let output = String::from_utf8_lossy(&output);
+  split_status(&output)
+    .map(|(output, status)| (output.to_string(), status))
+    .ok_or_else(|| AdbError::Fail(format!("unexpected output of `{}`: {}", cmd, output.trim())))
+}
+
+// splits the output of `cmd; echo $?` into the output of `cmd` and its status
+fn split_status(output: &str) -> Option<(&str, i32)> {
+  let output = output.strip_suffix('\n')?;
+  let (output, status) = match output.rfind('\n') {
+    Some(pos) => (&output[..pos], &output[pos + 1..]),
+    None => ("", output),
+  };
+  Some((output, status.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn split_status_output() {
+    let cases = [
+      ("target\n0\n", Some(("target", 0))),
+      ("0\n", Some(("", 0))),
+      ("1\n", Some(("", 1))),
+      ("line 1\nline 2\n\n2\n", Some(("line 1\nline 2\n", 2))),
+      ("no status\n", None),
+      ("0", None),
+      ("", None),
+    ];
+    for (output, expected) in cases.iter() {
+      assert_eq!(split_status(output), *expected, "{:?}", output);
+    }
+  }
+}
@@ -0,0 +1,265 @@
+//! A `std::fs`-like view of the device filesystem.
+//!
+//! Metadata, listings and file contents go through the sync protocol; the
+//! operations it lacks (removing, creating directories, renaming and changing
+//! permissions) run the equivalent shell commands.
+
+use std::io::{self, prelude::*};
+
+use super::client::*;
+use super::exec::exec_status;
+use super::list::AdbList;
+use super::pull::{not_found, readlink};
+use super::push::{AdbPush, PushOptions};
+use super::session::{SyncRecvReader, SyncSendWriter, SyncSession};
+use super::shell::ShellCommand;
+use crate::result::*;
+
+pub use super::list::{RemoteDirEntry, RemoteReadDir};
+pub use super::stat::RemoteMetadata;
+
+pub trait AdbFs {
+  /// Lists `path`, skipping the `.` and `..` entries.
+  ///
+  /// Unlike `AdbList::list_dir`, fails if `path` is not a directory.
+  fn read_dir(&mut self, path: &str) -> AdbResult<RemoteReadDir>;
+
+  /// Returns the metadata of `path`, following symlinks.
+  ///
+  /// Devices without `stat_v2` cannot follow symlinks; the metadata of the
+  /// symlink itself is returned instead.
+  fn metadata(&mut self, path: &str) -> AdbResult<RemoteMetadata>;
+
+  /// Returns the metadata of `path`, without following symlinks.
+  fn symlink_metadata(&mut self, path: &str) -> AdbResult<RemoteMetadata>;
+
+  /// Reads the whole contents of a file.
+  fn read(&mut self, path: &str) -> AdbResult<Vec<u8>>;
+
+  /// Reads the whole contents of a file into a string.
+  fn read_to_string(&mut self, path: &str) -> AdbResult<String>;
+
+  /// Creates or replaces a file with `contents`.
+  fn write<C: AsRef<[u8]>>(&mut self, path: &str, contents: C) -> AdbResult<()>;
+
+  /// Opens a file for reading, streaming its contents on demand.
+  fn open(&mut self, path: &str) -> AdbResult<RemoteFileReader>;
+
+  /// Creates or replaces a file, streaming what is written to it.
+  ///
+  /// The file is only written on the device once the writer is finished;
+  /// dropping it unfinished discards what was written.
+  fn create(&mut self, path: &str) -> AdbResult<RemoteFileWriter>;
+
+  /// Removes a file or symlink.
+  fn remove_file(&mut self, path: &str) -> AdbResult<()>;
+
+  /// Removes an empty directory.
+  fn remove_dir(&mut self, path: &str) -> AdbResult<()>;
+
+  /// Removes a directory and all of its contents.
+  fn remove_dir_all(&mut self, path: &str) -> AdbResult<()>;
+
+  /// Creates a directory, failing if it exists or its parent does not.
+  fn create_dir(&mut self, path: &str) -> AdbResult<()>;
+
+  /// Creates a directory and any missing parent.
+  fn create_dir_all(&mut self, path: &str) -> AdbResult<()>;
+
+  /// Renames `from` to `to`, replacing `to` if it is a file.
+  ///
+  /// This is `mv`: an existing directory `to` receives `from` instead.
+  fn rename(&mut self, from: &str, to: &str) -> AdbResult<()>;
+
+  /// Sets the permission bits (`mode & 0o7777`) of `path`.
+  fn set_permissions(&mut self, path: &str, mode: u32) -> AdbResult<()>;
+
+  /// Reads the target of a symlink.
+  fn read_link(&mut self, path: &str) -> AdbResult<String>;
+}
+
+impl AdbFs for AdbConnection {
+  fn read_dir(&mut self, path: &str) -> AdbResult<RemoteReadDir> {
+    let metadata = self.metadata(path)?;
+    if !metadata.is_dir() {
+      return Err(AdbError::Fail(format!(
+        "remote object '{}' is not a directory",
+        path
+      )));
+    }
+    self.list_dir(path)
+  }
+
+  fn metadata(&mut self, path: &str) -> AdbResult<RemoteMetadata> {
+    stat_existing(self, path, true)
+  }
+
+  fn symlink_metadata(&mut self, path: &str) -> AdbResult<RemoteMetadata> {
+    stat_existing(self, path, false)
+  }
+
+  fn read(&mut self, path: &str) -> AdbResult<Vec<u8>> {
+    let mut contents = vec![];
+    self.open(path)?.read_to_end(&mut contents)?;
+    Ok(contents)
+  }
+
+  fn read_to_string(&mut self, path: &str) -> AdbResult<String> {
+    String::from_utf8(self.read(path)?)
+      .map_err(|_| AdbError::Fail(format!("remote file '{}' is not valid UTF-8", path)))
+  }
+
+  fn write<C: AsRef<[u8]>>(&mut self, path: &str, contents: C) -> AdbResult<()> {
+    self.push_reader(contents.as_ref(), path, &PushOptions::default())
+  }
+
+  fn open(&mut self, path: &str) -> AdbResult<RemoteFileReader> {
+    let session = SyncSession::open(self)?;
+    let metadata = session.stat_follow(path)?;
+    if !metadata.exists() || metadata.is_dir() {
+      session.quit()?;
+      if metadata.is_dir() {
+        return Err(AdbError::Fail(format!(
+          "remote object '{}' is a directory",
+          path
+        )));
+      }
+      return Err(not_found(path, &metadata));
+    }
+
+    Ok(RemoteFileReader {
+      inner: session.into_recv(path)?,
+    })
+  }
+
+  fn create(&mut self, path: &str) -> AdbResult<RemoteFileWriter> {
+    let session = SyncSession::open(self)?;
+    Ok(RemoteFileWriter {
+      inner: session.into_send(path, &PushOptions::default())?,
+    })
+  }
+
+  fn remove_file(&mut self, path: &str) -> AdbResult<()> {
+    run(self, ShellCommand::new("rm").arg("--").arg(path))
+  }
+
+  fn remove_dir(&mut self, path: &str) -> AdbResult<()> {
+    run(self, ShellCommand::new("rmdir").arg("--").arg(path))
+  }
+
+  fn remove_dir_all(&mut self, path: &str) -> AdbResult<()> {
+    if !self.symlink_metadata(path)?.is_dir() {
+      return Err(AdbError::Fail(format!(
+        "remote object '{}' is not a directory",
+        path
+      )));
+    }
+    run(self, ShellCommand::new("rm").arg("-r").arg("--").arg(path))
+  }
+
+  fn create_dir(&mut self, path: &str) -> AdbResult<()> {
+    run(self, ShellCommand::new("mkdir").arg("--").arg(path))
+  }
+
+  fn create_dir_all(&mut self, path: &str) -> AdbResult<()> {
+    run(
+      self,
+      ShellCommand::new("mkdir").arg("-p").arg("--").arg(path),
+    )
+  }
+
+  fn rename(&mut self, from: &str, to: &str) -> AdbResult<()> {
+    run(
+      self,
+      ShellCommand::new("mv")
+        .arg("-f")
+        .arg("--")
+        .arg(from)
+        .arg(to),
+    )
+  }
+
+  fn set_permissions(&mut self, path: &str, mode: u32) -> AdbResult<()> {
+    run(
+      self,
+      ShellCommand::new("chmod")
+        .arg(format!("{:o}", mode & 0o7777))
+        .arg("--")
+        .arg(path),
+    )
+  }
+
+  fn read_link(&mut self, path: &str) -> AdbResult<String> {
+    readlink(self, path)
+  }
+}
+
+/// Stats `path`, failing if it does not exist.
+fn stat_existing(conn: &mut AdbConnection, path: &str, follow: bool) -> AdbResult<RemoteMetadata> {
+  let session = SyncSession::open(conn)?;
+  let metadata = if follow {
+    session.stat_follow(path)?
+  } else {
+    session.stat(path)?
+  };
+  session.quit()?;
+  if !metadata.exists() {
+    return Err(not_found(path, &metadata));
+  }
+  Ok(metadata)
+}
+
+/// Runs `cmd`, failing with its output if it exits with a non-zero status.
+fn run(conn: &mut AdbConnection, cmd: ShellCommand) -> AdbResult<()> {
+  let line = format!("{} 2>&1", cmd.command_line());
+  debug!("fs: {}", line);
+  match exec_status(conn, &line)? {
+    (_, 0) => Ok(()),
+    (message, status) if message.trim().is_empty() => Err(AdbError::Fail(format!(
+      "`{}` exited with status {}",
+      cmd.command_line(),
+      status
+    ))),
+    (message, _) => Err(AdbError::Fail(message.trim().to_string())),
+  }
+}
+
+/// A remote file opened for reading with `AdbFs::open`.
+///
+/// Owns a sync session of its own, receiving the file as it is read.
+/// Dropping the reader before EOF closes the stream.
+pub struct RemoteFileReader {
+  inner: SyncRecvReader,
+}
+
+impl Read for RemoteFileReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.inner.read(buf)
+  }
+}
+
+/// A remote file opened for writing with `AdbFs::create`.
+///
+/// Owns a sync session of its own, sending what is written as it goes. The
+/// file is only committed by `finish`: dropping the writer closes the stream,
+/// and the device deletes the partial file.
+pub struct RemoteFileWriter {
+  inner: SyncSendWriter,
+}
+
+impl RemoteFileWriter {
+  /// Sends the remaining data and waits for the device to commit the file.
+  pub fn finish(self) -> AdbResult<()> {
+    self.inner.finish()
+  }
+}
+
+impl Write for RemoteFileWriter {
+  fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+    self.inner.write(data)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.inner.flush()
+  }
+}
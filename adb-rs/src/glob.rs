@@ -0,0 +1,223 @@
+//! Wildcard sources for `AdbPull::pull_glob` and `AdbPush::push_glob`.
+//!
+//! Patterns support `*`, `?` and `[...]` within a path component, as in a
+//! shell, and wildcards do not match a leading `.`. Remote patterns are
+//! expanded with sync `LIST` requests rather than by a device shell, so
+//! file names need no quoting.
+//!
+//! A wildcard character is matched literally by enclosing it in brackets:
+//! `IMG [[]1].jpg` matches `IMG [1].jpg`, and `[*]` matches `*`. `adb-cli`
+//! only expands a path containing wildcards if it does not exist as written.
+
+use ::glob::{MatchOptions, Pattern};
+use std::io;
+use std::path::PathBuf;
+
+use super::list::RemoteDirEntry;
+use super::session::SyncSession;
+use crate::result::*;
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+  case_sensitive: true,
+  require_literal_separator: true,
+  require_literal_leading_dot: true,
+};
+
+/// Whether `pattern` contains wildcards.
+pub fn is_glob(pattern: &str) -> bool {
+  pattern.contains(['*', '?', '['])
+}
+
+/// Lists the remote paths matching `pattern`, sorted by path.
+///
+/// Only the components from the first one with wildcards on are listed; a
+/// pattern without wildcards matches the path itself if it exists.
+pub(crate) fn expand_remote(
+  session: &SyncSession,
+  pattern: &str,
+) -> AdbResult<Vec<RemoteDirEntry>> {
+  let (base, components) = match split_pattern(pattern) {
+    Some(split) => split,
+    None => {
+      let metadata = session.stat(pattern)?;
+      if !metadata.exists() {
+        return Err(no_matches(pattern));
+      }
+      let name = pattern
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or("");
+      return Ok(vec![RemoteDirEntry::new(
+        pattern.to_string(),
+        name.to_string(),
+        metadata,
+      )]);
+    }
+  };
+
+  let patterns = components
+    .iter()
+    .map(|c| {
+      Pattern::new(c)
+        .map_err(|err| AdbError::Fail(format!("invalid pattern '{}': {}", pattern, err)))
+    })
+    .collect::<AdbResult<Vec<_>>>()?;
+
+  let mut dirs = vec![base];
+  let mut matches = vec![];
+  for (i, component) in patterns.iter().enumerate() {
+    let is_last = i == patterns.len() - 1;
+    let mut next = vec![];
+
+    for dir in &dirs {
+      let list_path = if dir.is_empty() { "." } else { dir };
+      for entry in session.list(list_path)? {
+        if !component.matches_with(entry.name(), MATCH_OPTIONS) {
+          continue;
+        }
+        let path = join(dir, entry.name());
+        if is_last {
+          matches.push(RemoteDirEntry::new(
+            path,
+            entry.name().to_string(),
+            *entry.metadata(),
+          ));
+        } else if entry.metadata().is_dir() || entry.metadata().is_symlink() {
+          // listing a symlink lists the directory it points to, if any
+          next.push(path);
+        }
+      }
+    }
+
+    dirs = next;
+  }
+  debug!("glob: {}: {} matches", pattern, matches.len());
+
+  if matches.is_empty() {
+    return Err(no_matches(pattern));
+  }
+  matches.sort_by(|a, b| a.path().cmp(b.path()));
+  Ok(matches)
+}
+
+/// Splits `pattern` before its first component with wildcards, into the
+/// directory to list from (empty for the current one) and the non-empty
+/// components from there on. Returns `None` without wildcards.
+fn split_pattern(pattern: &str) -> Option<(String, Vec<&str>)> {
+  let components: Vec<&str> = pattern.split('/').collect();
+  let first = components.iter().position(|c| is_glob(c))?;
+  let base = match components[..first].join("/") {
+    ref base if base.is_empty() && first > 0 => "/".to_string(),
+    base => base,
+  };
+  let rest = components[first..]
+    .iter()
+    .cloned()
+    .filter(|c| !c.is_empty())
+    .collect();
+  Some((base, rest))
+}
+
+/// Lists the local paths matching `pattern`, sorted by path.
+pub(crate) fn expand_local(pattern: &str) -> AdbResult<Vec<PathBuf>> {
+  let paths = ::glob::glob_with(pattern, MATCH_OPTIONS)
+    .map_err(|err| AdbError::Fail(format!("invalid pattern '{}': {}", pattern, err)))?
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(io::Error::from)?;
+  debug!("glob: {}: {} matches", pattern, paths.len());
+
+  if paths.is_empty() {
+    return Err(no_matches(pattern));
+  }
+  Ok(paths)
+}
+
+fn join(dir: &str, name: &str) -> String {
+  if dir.is_empty() {
+    name.to_string()
+  } else if dir.ends_with('/') {
+    format!("{}{}", dir, name)
+  } else {
+    format!("{}/{}", dir, name)
+  }
+}
+
+fn no_matches(pattern: &str) -> AdbError {
+  AdbError::Fail(format!("no matches for '{}'", pattern))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn split_absolute() {
+    assert_eq!(
+      split_pattern("/sdcard/*.jpg"),
+      Some(("/sdcard".to_string(), vec!["*.jpg"]))
+    );
+    assert_eq!(split_pattern("/*"), Some(("/".to_string(), vec!["*"])));
+    assert_eq!(
+      split_pattern("/sdcard/DCIM/Camera/IMG_[0-9]?.jpg"),
+      Some(("/sdcard/DCIM/Camera".to_string(), vec!["IMG_[0-9]?.jpg"]))
+    );
+  }
+
+  #[test]
+  fn split_relative() {
+    assert_eq!(split_pattern("*/x"), Some(("".to_string(), vec!["*", "x"])));
+    assert_eq!(split_pattern("a/*"), Some(("a".to_string(), vec!["*"])));
+  }
+
+  #[test]
+  fn split_keeps_literal_components_after_wildcards() {
+    // trailing and repeated slashes leave no empty component to match
+    assert_eq!(
+      split_pattern("/a/*/b/"),
+      Some(("/a".to_string(), vec!["*", "b"]))
+    );
+    assert_eq!(
+      split_pattern("/a//*//b"),
+      Some(("/a/".to_string(), vec!["*", "b"]))
+    );
+  }
+
+  #[test]
+  fn split_without_wildcards() {
+    assert_eq!(split_pattern("/sdcard/a.jpg"), None);
+    assert_eq!(split_pattern("a"), None);
+    assert_eq!(split_pattern("/"), None);
+    assert!(!is_glob("/sdcard/a.jpg"));
+    assert!(is_glob("[ab]"));
+  }
+
+  #[test]
+  fn join_paths() {
+    assert_eq!(join("", "x"), "x");
+    assert_eq!(join("/", "sdcard"), "/sdcard");
+    assert_eq!(join("/a/", "b"), "/a/b");
+    assert_eq!(join("/sdcard", "a.jpg"), "/sdcard/a.jpg");
+  }
+
+  #[test]
+  fn wildcards_skip_hidden_files() {
+    let star = Pattern::new("*").unwrap();
+    assert!(star.matches_with("a.jpg", MATCH_OPTIONS));
+    assert!(!star.matches_with(".nomedia", MATCH_OPTIONS));
+    assert!(Pattern::new(".*")
+      .unwrap()
+      .matches_with(".nomedia", MATCH_OPTIONS));
+    assert!(!Pattern::new("A*").unwrap().matches_with("a", MATCH_OPTIONS));
+  }
+
+  #[test]
+  fn brackets_escape_wildcards() {
+    let pattern = Pattern::new("IMG [[]1].jpg").unwrap();
+    assert!(pattern.matches_with("IMG [1].jpg", MATCH_OPTIONS));
+    assert!(!pattern.matches_with("IMG 1.jpg", MATCH_OPTIONS));
+    let pattern = Pattern::new("a[*][?]").unwrap();
+    assert!(pattern.matches_with("a*?", MATCH_OPTIONS));
+    assert!(!pattern.matches_with("ab?", MATCH_OPTIONS));
+  }
+}
@@ -12,9 +12,22 @@ mod message;
 mod utils;
 
 mod client;
+mod parallel;
 mod sync;
+mod verify;
 
+pub mod compression;
+pub mod exec;
+pub mod fs;
+pub mod glob;
+pub mod list;
+pub mod progress;
+pub mod props;
+pub mod pull;
 pub mod push;
+pub mod session;
 pub mod shell;
+pub mod stat;
+pub mod sync_dir;
 
-pub use self::client::{AdbClient, AdbConnection};
+pub use self::client::{AdbClient, AdbConnection, CancelHandle};
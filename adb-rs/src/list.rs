@@ -0,0 +1,114 @@
+use super::client::*;
+use super::stat::RemoteMetadata;
+use super::sync::*;
+use crate::result::*;
+
+/// An entry of a remote directory, as reported by the sync `LIST` request
+/// (or `LIS2` on devices advertising `ls_v2`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteDirEntry {
+  path: String,
+  name: String,
+  metadata: RemoteMetadata,
+}
+
+impl RemoteDirEntry {
+  pub(crate) fn new(path: String, name: String, metadata: RemoteMetadata) -> Self {
+    RemoteDirEntry {
+      path,
+      name,
+      metadata,
+    }
+  }
+
+  /// Full path of the entry, the listed directory joined with the name.
+  pub fn path(&self) -> &str {
+    &self.path
+  }
+
+  /// File name of the entry, without the directory.
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Metadata of the entry. Symlinks are not followed.
+  pub fn metadata(&self) -> &RemoteMetadata {
+    &self.metadata
+  }
+}
+
+/// Iterator over the entries of a remote directory.
+#[derive(Debug)]
+pub struct RemoteReadDir {
+  entries: ::std::vec::IntoIter<RemoteDirEntry>,
+}
+
+impl Iterator for RemoteReadDir {
+  type Item = RemoteDirEntry;
+
+  fn next(&mut self) -> Option<RemoteDirEntry> {
+    self.entries.next()
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.entries.size_hint()
+  }
+}
+
+pub trait AdbList {
+  /// Lists `remote_path`, skipping the `.` and `..` entries.
+  ///
+  /// A missing or unreadable directory yields no entries.
+  fn list_dir(&mut self, remote_path: &str) -> AdbResult<RemoteReadDir>;
+}
+
+impl AdbList for AdbConnection {
+  fn list_dir(&mut self, remote_path: &str) -> AdbResult<RemoteReadDir> {
+    let ls_v2 = self.has_feature("ls_v2");
+    let stream = self.open_stream("sync:")?;
+    let entries = list(&stream, remote_path, ls_v2)?;
+    stream.sync_quit()?;
+    Ok(RemoteReadDir {
+      entries: entries.into_iter(),
+    })
+  }
+}
+
+/// Sends `LIST`, or `LIS2` if `ls_v2` is set, and collects the entries.
+pub(crate) fn list(
+  stream: &AdbStream,
+  remote_path: &str,
+  ls_v2: bool,
+) -> AdbResult<Vec<RemoteDirEntry>> {
+  let list = if ls_v2 {
+    debug!("LIS2");
+    SyncCommand::new_list_v2(remote_path)
+  } else {
+    debug!("LIST");
+    SyncCommand::new_list(remote_path)
+  };
+  let mut reader = SyncReader::new(stream);
+  stream.send(AdbStreamPacket::new_write(list))?;
+  reader.wait_ok()?;
+
+  let mut entries = vec![];
+
+  loop {
+    let (name, metadata) = match reader.read_list_record(ls_v2)? {
+      SyncRecord::Dent { name, metadata } => (name, metadata),
+      SyncRecord::Done => break,
+      record => return Err(record.into_error()),
+    };
+
+    if name == "." || name == ".." {
+      continue;
+    }
+
+    let path = format!("{}/{}", remote_path.trim_end_matches('/'), name);
+    entries.push(RemoteDirEntry::new(path, name, metadata));
+  }
+
+  debug!("LIST done: {} entries", entries.len());
+
+  Ok(entries)
+}
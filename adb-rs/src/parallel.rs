@@ -0,0 +1,81 @@
+use std::sync::Mutex;
+use std::thread;
+
+use super::client::*;
+use super::compression::Compression;
+use super::progress::TotalProgress;
+use super::session::SyncSession;
+use crate::result::*;
+
+/// Runs `transfer` on every job over up to `concurrency` sync sessions at
+/// once, using `compression`, returning the jobs with their results in their
+/// original order.
+///
+/// Each thread owns a session, reopened after a failed transfer; failing to
+/// open one fails the job it was for. `total` is the size of all jobs, if
+/// known, reported with `TransferObserver::total_progress`.
+pub(crate) fn transfer_all<T, F>(
+  conn: &AdbConnection,
+  jobs: Vec<T>,
+  total: Option<u64>,
+  concurrency: usize,
+  compression: Compression,
+  transfer: F,
+) -> Vec<(T, AdbResult<u64>)>
+where
+  T: Send,
+  F: Fn(&SyncSession, &T) -> AdbResult<u64> + Sync,
+{
+  let threads = concurrency.min(jobs.len()).max(1);
+  let totals = conn
+    .transfer_observer()
+    .map(|observer| TotalProgress::start(observer.clone(), total));
+  let queue = Mutex::new(jobs.into_iter().enumerate());
+  let results = Mutex::new(vec![]);
+
+  let work = || {
+    let mut session: Option<SyncSession> = None;
+    let observer = totals.as_ref().map(|totals| totals.session_observer());
+
+    loop {
+      let (index, job) = match queue.lock().unwrap().next() {
+        Some(next) => next,
+        None => break,
+      };
+
+      let result = SyncSession::ensure_open(&mut session, conn).and_then(|session| {
+        session.set_compression(compression);
+        if observer.is_some() {
+          session.set_observer(observer.clone());
+        }
+        transfer(session, &job)
+      });
+      results.lock().unwrap().push((index, job, result));
+    }
+
+    // every job is done, a failing `QUIT` changes none of their results
+    if let Some(session) = session {
+      if let Err(err) = session.quit() {
+        debug!("failed to end the sync session: {}", err);
+      }
+    }
+  };
+
+  if threads == 1 {
+    work();
+  } else {
+    debug!("transferring over {} sync streams", threads);
+    thread::scope(|scope| {
+      for _ in 0..threads {
+        scope.spawn(work);
+      }
+    });
+  }
+
+  let mut results = results.into_inner().unwrap();
+  results.sort_by_key(|&(index, _, _)| index);
+  results
+    .into_iter()
+    .map(|(_, job, result)| (job, result))
+    .collect()
+}
@@ -0,0 +1,132 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::result::*;
+
+/// Receives progress notifications of sync file transfers.
+///
+/// Set it with `AdbConnection::set_transfer_observer` to observe `push`,
+/// `pull` and their recursive variants, or `SyncSession::set_observer` for a
+/// single session. All methods default to doing nothing.
+pub trait TransferObserver: Send + Sync {
+  /// A file transfer is starting. `total` is its size in bytes, if known.
+  fn started(&self, _remote_path: &str, _total: Option<u64>) {}
+
+  /// `transferred` bytes of the file have been sent or received so far.
+  fn progress(&self, _remote_path: &str, _transferred: u64, _total: Option<u64>) {}
+
+  /// The file transfer has ended, with the number of bytes transferred.
+  fn finished(&self, _remote_path: &str, _result: &AdbResult<u64>) {}
+
+  /// `transferred` bytes of all the files of a recursive push or pull have
+  /// been sent or received so far. `total` is their size, if known.
+  ///
+  /// With several concurrent streams (see `PushOptions::concurrency`)
+  /// the per-file notifications of different files interleave; this is the
+  /// overall progress.
+  fn total_progress(&self, _transferred: u64, _total: Option<u64>) {}
+}
+
+impl fmt::Debug for dyn TransferObserver {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("TransferObserver")
+  }
+}
+
+/// Tracks one file transfer, notifying the observer if there is one.
+pub(crate) struct Progress<'a> {
+  observer: Option<&'a dyn TransferObserver>,
+  remote_path: &'a str,
+  total: Option<u64>,
+  transferred: u64,
+}
+
+impl<'a> Progress<'a> {
+  pub(crate) fn start(
+    observer: Option<&'a dyn TransferObserver>,
+    remote_path: &'a str,
+    total: Option<u64>,
+  ) -> Self {
+    if let Some(observer) = observer {
+      observer.started(remote_path, total);
+    }
+    Progress {
+      observer,
+      remote_path,
+      total,
+      transferred: 0,
+    }
+  }
+
+  pub(crate) fn advance(&mut self, n: u64) {
+    self.transferred += n;
+    if let Some(observer) = self.observer {
+      observer.progress(self.remote_path, self.transferred, self.total);
+    }
+  }
+
+  pub(crate) fn finish(self, result: &AdbResult<u64>) {
+    if let Some(observer) = self.observer {
+      observer.finished(self.remote_path, result);
+    }
+  }
+}
+
+/// Sums the progress of the files of a recursive transfer.
+pub(crate) struct TotalProgress {
+  observer: Arc<dyn TransferObserver>,
+  transferred: AtomicU64,
+  total: Option<u64>,
+}
+
+impl TotalProgress {
+  pub(crate) fn start(observer: Arc<dyn TransferObserver>, total: Option<u64>) -> Arc<Self> {
+    observer.total_progress(0, total);
+    Arc::new(TotalProgress {
+      observer,
+      transferred: AtomicU64::new(0),
+      total,
+    })
+  }
+
+  /// An observer for a session, which transfers one file at a time.
+  pub(crate) fn session_observer(self: &Arc<Self>) -> Arc<dyn TransferObserver> {
+    Arc::new(SessionProgress {
+      totals: self.clone(),
+      transferred: AtomicU64::new(0),
+    })
+  }
+
+  fn advance(&self, n: u64) {
+    let transferred = self.transferred.fetch_add(n, Ordering::SeqCst) + n;
+    self.observer.total_progress(transferred, self.total);
+  }
+}
+
+// forwards to the observer of the transfer, adding to its total
+struct SessionProgress {
+  totals: Arc<TotalProgress>,
+  // of the current file
+  transferred: AtomicU64,
+}
+
+impl TransferObserver for SessionProgress {
+  fn started(&self, remote_path: &str, total: Option<u64>) {
+    self.transferred.store(0, Ordering::SeqCst);
+    self.totals.observer.started(remote_path, total);
+  }
+
+  fn progress(&self, remote_path: &str, transferred: u64, total: Option<u64>) {
+    let last = self.transferred.swap(transferred, Ordering::SeqCst);
+    self
+      .totals
+      .observer
+      .progress(remote_path, transferred, total);
+    self.totals.advance(transferred.saturating_sub(last));
+  }
+
+  fn finished(&self, remote_path: &str, result: &AdbResult<u64>) {
+    self.totals.observer.finished(remote_path, result);
+  }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::client::*;
+use super::shell::{AdbShell, ShellCommand, ShellOutput};
+use crate::result::*;
+
+/// Device system properties, as printed by `getprop`.
+#[derive(Debug, Clone, Default)]
+pub struct Properties {
+  map: HashMap<String, String>,
+}
+
+impl Properties {
+  /// Parses the `[key]: [value]` lines printed by `getprop`.
+  ///
+  /// Values spanning several lines are joined with `\n`.
+  pub fn parse(output: &str) -> Self {
+    let mut map = HashMap::new();
+    let mut lines = output.lines();
+
+    while let Some(line) = lines.next() {
+      let line = line.trim_end_matches('\r');
+      if !line.starts_with('[') {
+        continue;
+      }
+
+      let key_end = match line.find("]: [") {
+        Some(pos) => pos,
+        None => continue,
+      };
+      let key = &line[1..key_end];
+      let mut value = line[key_end + 4..].to_string();
+
+      while !value.ends_with(']') {
+        match lines.next() {
+          Some(next) => {
+            value.push('\n');
+            value.push_str(next.trim_end_matches('\r'));
+          }
+          None => break,
+        }
+      }
+
+      if value.ends_with(']') {
+        value.pop();
+      }
+      map.insert(key.to_string(), value);
+    }
+
+    Properties { map }
+  }
+
+  pub fn get(&self, key: &str) -> Option<&str> {
+    self.map.get(key).map(String::as_str)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+    self.map.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+  }
+
+  pub fn len(&self) -> usize {
+    self.map.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.map.is_empty()
+  }
+
+  /// `ro.build.version.sdk`
+  pub fn sdk_level(&self) -> Option<u32> {
+    self
+      .get("ro.build.version.sdk")
+      .and_then(|v| v.trim().parse().ok())
+  }
+
+  /// `ro.product.cpu.abilist`, falling back to `ro.product.cpu.abi` on
+  /// devices that predate ABI lists.
+  pub fn abi_list(&self) -> Vec<String> {
+    self
+      .get("ro.product.cpu.abilist")
+      .filter(|v| !v.is_empty())
+      .or_else(|| self.get("ro.product.cpu.abi"))
+      .map(|v| {
+        v.split(',')
+          .filter(|abi| !abi.is_empty())
+          .map(ToString::to_string)
+          .collect()
+      })
+      .unwrap_or_default()
+  }
+
+  /// `ro.build.fingerprint`
+  pub fn build_fingerprint(&self) -> Option<&str> {
+    self.get("ro.build.fingerprint")
+  }
+
+  /// `ro.serialno`, falling back to `ro.boot.serialno`.
+  pub fn serial(&self) -> Option<&str> {
+    self
+      .get("ro.serialno")
+      .filter(|v| !v.is_empty())
+      .or_else(|| self.get("ro.boot.serialno"))
+  }
+}
+
+pub trait AdbProps {
+  fn getprops(&mut self) -> AdbResult<Properties>;
+  /// Returns `None` if the property is unset or empty.
+  fn getprop(&mut self, key: &str) -> AdbResult<Option<String>>;
+  /// Fails if `setprop` exits with a non-zero status or, on devices without
+  /// shell protocol v2 where the status is unknown, prints anything.
+  fn setprop(&mut self, key: &str, value: &str) -> AdbResult<()>;
+  /// Polls `key` until it equals `value`, e.g. `sys.boot_completed` = `1`.
+  fn wait_for_prop(&mut self, key: &str, value: &str, timeout: Duration) -> AdbResult<()>;
+}
+
+const WAIT_FOR_PROP_INTERVAL: Duration = Duration::from_millis(500);
+
+impl AdbProps for AdbConnection {
+  fn getprops(&mut self) -> AdbResult<Properties> {
+    let output = self.shell_exec("getprop")?;
+    Ok(Properties::parse(&String::from_utf8_lossy(&output)))
+  }
+
+  fn getprop(&mut self, key: &str) -> AdbResult<Option<String>> {
+    getprop_deadline(self, key, None)
+  }
+
+  fn setprop(&mut self, key: &str, value: &str) -> AdbResult<()> {
+    let cmd = ShellCommand::new("setprop").arg(key).arg(value);
+    let ShellOutput { output, exit_code } = self.shell_output(&cmd.command_line())?;
+    let message = String::from_utf8_lossy(&output).trim_end().to_string();
+    // without shell protocol v2, output is the only sign of failure
+    let failed = match exit_code {
+      Some(code) => code != 0,
+      None => !message.is_empty(),
+    };
+    if failed {
+      return Err(AdbError::Fail(if message.is_empty() {
+        format!("`{}` exited with status {}", cmd, exit_code.unwrap_or(0))
+      } else {
+        message
+      }));
+    }
+    Ok(())
+  }
+
+  fn wait_for_prop(&mut self, key: &str, value: &str, timeout: Duration) -> AdbResult<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+      if getprop_deadline(self, key, Some(deadline))?.as_deref() == Some(value) {
+        return Ok(());
+      }
+
+      let now = Instant::now();
+      if now >= deadline {
+        return Err(AdbError::Timeout);
+      }
+      thread::sleep(::std::cmp::min(WAIT_FOR_PROP_INTERVAL, deadline - now));
+    }
+  }
+}
+
+/// `AdbProps::getprop`, failing with `AdbError::Timeout` if `getprop` is
+/// still running at `deadline`.
+fn getprop_deadline(
+  conn: &mut AdbConnection,
+  key: &str,
+  deadline: Option<Instant>,
+) -> AdbResult<Option<String>> {
+  let cmd = ShellCommand::new("getprop").arg(key);
+  let mut stream = conn.shell_stream(&cmd.command_line())?;
+  stream.set_deadline(deadline);
+  let output = stream.read_all()?;
+  let value = String::from_utf8_lossy(&output)
+    .trim_end_matches(&['\r', '\n'][..])
+    .to_string();
+  if value.is_empty() {
+    Ok(None)
+  } else {
+    Ok(Some(value))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_getprop_output() {
+    let props = Properties::parse(
+      "[ro.build.version.sdk]: [30]\r\n\
+       [ro.product.cpu.abi]: [arm64-v8a]\n\
+       [empty]: []\n\
+       [with]: [brackets]: [inside]\n",
+    );
+    assert_eq!(props.len(), 4);
+    assert_eq!(props.get("ro.build.version.sdk"), Some("30"));
+    assert_eq!(props.sdk_level(), Some(30));
+    assert_eq!(props.get("empty"), Some(""));
+    assert_eq!(props.get("with"), Some("brackets]: [inside"));
+    assert_eq!(props.get("missing"), None);
+  }
+
+  #[test]
+  fn parse_multiline_values() {
+    let props = Properties::parse("[motd]: [line 1\r\nline 2\n]\n[next]: [1]\n");
+    assert_eq!(props.get("motd"), Some("line 1\nline 2\n"));
+    assert_eq!(props.get("next"), Some("1"));
+  }
+
+  #[test]
+  fn parse_skips_malformed_lines() {
+    let props = Properties::parse("garbage\n[no separator]\n\n[ok]: [yes]\n[unterminated]: [x");
+    assert_eq!(props.len(), 2);
+    assert_eq!(props.get("ok"), Some("yes"));
+    assert_eq!(props.get("unterminated"), Some("x"));
+    assert!(Properties::parse("").is_empty());
+  }
+
+  #[test]
+  fn fallbacks() {
+    let props = Properties::parse(
+      "[ro.product.cpu.abilist]: []\n\
+       [ro.product.cpu.abi]: [armeabi-v7a]\n\
+       [ro.serialno]: []\n\
+       [ro.boot.serialno]: [ABC123]\n",
+    );
+    assert_eq!(props.abi_list(), vec!["armeabi-v7a"]);
+    assert_eq!(props.serial(), Some("ABC123"));
+
+    let props = Properties::parse("[ro.product.cpu.abilist]: [arm64-v8a,armeabi-v7a,]\n");
+    assert_eq!(props.abi_list(), vec!["arm64-v8a", "armeabi-v7a"]);
+    assert_eq!(props.serial(), None);
+    assert_eq!(props.sdk_level(), None);
+  }
+}
@@ -0,0 +1,749 @@
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::{self, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use super::client::*;
+use super::compression::{decoder, Codec, Compression};
+use super::exec::{exec_status, AdbExec};
+use super::glob::expand_remote;
+use super::parallel::transfer_all;
+use super::progress::Progress;
+use super::session::SyncSession;
+use super::shell::ShellCommand;
+use super::stat::{stat_follow_exec, RemoteMetadata};
+use super::sync::*;
+use super::verify::{verify, HashingReader};
+use crate::result::*;
+
+/// How files are pulled.
+#[derive(Debug, Clone)]
+pub struct PullOptions {
+  /// Sets the mtime of pulled files and directories to the device's. `pull`
+  /// always does.
+  pub preserve_mtime: bool,
+  /// Sets the permission bits of pulled files and directories to the
+  /// device's (Unix only).
+  pub preserve_mode: bool,
+  /// Pulls the files and directories symlinks point to, instead of
+  /// recreating the symlinks themselves.
+  pub follow_symlinks: bool,
+  pub compression: Compression,
+  /// How many sync streams `pull_dir` and `pull_glob` spread files across,
+  /// each transferring one file at a time.
+  pub concurrency: usize,
+}
+
+impl Default for PullOptions {
+  fn default() -> Self {
+    PullOptions {
+      preserve_mtime: false,
+      preserve_mode: false,
+      follow_symlinks: false,
+      compression: Compression::None,
+      concurrency: 1,
+    }
+  }
+}
+
+pub trait AdbPull {
+  fn pull_writer<W: Write>(&mut self, remote_path: &str, w: W) -> AdbResult<()>;
+  /// Pulls a remote file, keeping its mtime.
+  ///
+  /// A symlink is recreated locally, unless `options` follows symlinks.
+  fn pull<P: AsRef<Path>>(
+    &mut self,
+    remote_path: &str,
+    local_path: P,
+    options: &PullOptions,
+  ) -> AdbResult<()>;
+  /// Completes a partial `local_path` with the rest of `remote_path`, then
+  /// checks its size and compares its hash with one computed on the device.
+  ///
+  /// The missing byte range is read with `tail -c` through `exec:`; a missing
+  /// local file is fetched whole. Returns the number of bytes fetched. On
+  /// `AdbError::ChecksumMismatch` the local file is kept as is, pull it
+  /// again without resuming.
+  fn pull_resume<P: AsRef<Path>>(&mut self, remote_path: &str, local_path: P) -> AdbResult<u64>;
+  /// Pulls the tree under `remote_dir` into `local_dir` over one sync stream.
+  /// Files are spread across several streams if `options.concurrency` is
+  /// above 1.
+  ///
+  /// Regular files are received, directories and symlinks are recreated, and
+  /// other file types are skipped. If `options` follows symlinks, the
+  /// files and directories they point to are pulled instead, skipping links
+  /// back to a directory being pulled. A failing file or directory does not
+  /// abort the transfer; its error is reported in the returned summary.
+  fn pull_dir<P: AsRef<Path>>(
+    &mut self,
+    remote_dir: &str,
+    local_dir: P,
+    options: &PullOptions,
+  ) -> AdbResult<PullDirSummary>;
+  /// Pulls the remote paths matching `pattern` into `local_dir` over one
+  /// sync stream, or several as with `pull_dir`; see `glob` for the syntax.
+  ///
+  /// Matching directories are pulled recursively, as with `pull_dir`. Fails
+  /// if nothing matches.
+  fn pull_glob<P: AsRef<Path>>(
+    &mut self,
+    pattern: &str,
+    local_dir: P,
+    options: &PullOptions,
+  ) -> AdbResult<PullDirSummary>;
+}
+
+/// Outcome of pulling a single file or symlink during `pull_dir` or
+/// `pull_glob`.
+#[derive(Debug)]
+pub struct PullFileResult {
+  pub remote_path: String,
+  pub local_path: PathBuf,
+  /// Number of bytes received.
+  pub result: AdbResult<u64>,
+}
+
+/// A directory that could not be created locally, listed or given its
+/// metadata during `pull_dir` or `pull_glob`.
+#[derive(Debug)]
+pub struct PullDirError {
+  pub remote_path: String,
+  pub local_path: PathBuf,
+  pub error: AdbError,
+}
+
+/// Per-file results and totals of a `pull_dir` or `pull_glob`.
+#[derive(Debug, Default)]
+pub struct PullDirSummary {
+  pub files: Vec<PullFileResult>,
+  /// The contents of a directory that could not be listed are missing from
+  /// `files`.
+  pub failed_dirs: Vec<PullDirError>,
+}
+
+impl PullDirSummary {
+  pub fn total_files(&self) -> usize {
+    self.files.len()
+  }
+
+  pub fn total_bytes(&self) -> u64 {
+    self
+      .files
+      .iter()
+      .filter_map(|f| f.result.as_ref().ok())
+      .sum()
+  }
+
+  pub fn failed(&self) -> impl Iterator<Item = &PullFileResult> {
+    self.files.iter().filter(|f| f.result.is_err())
+  }
+
+  pub fn is_success(&self) -> bool {
+    self.failed().next().is_none() && self.failed_dirs.is_empty()
+  }
+}
+
+impl AdbPull for AdbConnection {
+  fn pull_writer<W: Write>(&mut self, remote_path: &str, w: W) -> AdbResult<()> {
+    let session = SyncSession::open(self)?;
+    session.recv(remote_path, w)?;
+    session.quit()
+  }
+
+  fn pull<P: AsRef<Path>>(
+    &mut self,
+    remote_path: &str,
+    local_path: P,
+    options: &PullOptions,
+  ) -> AdbResult<()> {
+    let mut session = SyncSession::open(self)?;
+    session.set_compression(options.compression);
+
+    let mut metadata = session.stat(remote_path)?;
+    if metadata.is_symlink() {
+      if !options.follow_symlinks {
+        session.quit()?;
+        let target = readlink(self, remote_path)?;
+        debug!("pull: {} is a symlink to {}", remote_path, target);
+        return replace_with_symlink(&target, local_path.as_ref());
+      }
+      metadata = stat_follow(self, &session, remote_path)?;
+    }
+    if !metadata.exists() {
+      session.quit()?;
+      return Err(not_found(remote_path, &metadata));
+    }
+
+    let (file, _) = recv_file(
+      &session,
+      remote_path,
+      local_path.as_ref(),
+      Some(metadata.len()),
+    )?;
+
+    session.quit()?;
+
+    file.set_modified(metadata.modified())?;
+    if options.preserve_mode {
+      set_mode(local_path.as_ref(), metadata.permissions())?;
+    }
+
+    Ok(())
+  }
+
+  fn pull_resume<P: AsRef<Path>>(&mut self, remote_path: &str, local_path: P) -> AdbResult<u64> {
+    let local_path = local_path.as_ref();
+
+    let session = SyncSession::open(self)?;
+    let metadata = stat_follow(self, &session, remote_path)?;
+    session.quit()?;
+    if !metadata.exists() {
+      return Err(not_found(remote_path, &metadata));
+    }
+    if !metadata.is_file() {
+      return Err(AdbError::Fail(format!(
+        "remote object '{}' is not a regular file",
+        remote_path
+      )));
+    }
+    let size = metadata.len();
+
+    let offset = match fs::metadata(local_path) {
+      Ok(local) => local.len(),
+      Err(ref err) if err.kind() == ErrorKind::NotFound => 0,
+      Err(err) => return Err(err.into()),
+    };
+    if offset > size {
+      return Err(AdbError::Fail(format!(
+        "local file '{}' is larger than remote file '{}' ({} > {} bytes)",
+        local_path.display(),
+        remote_path,
+        offset,
+        size
+      )));
+    }
+
+    let mut fetched = 0;
+    if offset < size {
+      debug!(
+        "pull: resuming {} at 0x{:x} of 0x{:x}",
+        remote_path, offset, size
+      );
+      // errors must not end up in the file, a failed `tail` is a short read
+      let cmd = ShellCommand::new("tail")
+        .arg("-c")
+        .arg(format!("+{}", offset + 1))
+        .arg("--")
+        .arg(remote_path);
+      let stream = self.exec_stream(&format!("{} 2>/dev/null", cmd.command_line()))?;
+
+      let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(local_path)?;
+      let mut w = BufWriter::new(file);
+      let observer = self.transfer_observer().map(|observer| observer.as_ref());
+      let mut progress = Progress::start(observer, remote_path, Some(size - offset));
+      // the file may have grown since, only the stat'd size is fetched
+      let result = copy(stream.take(size - offset), &mut w, &mut progress);
+      progress.finish(&result);
+      fetched = result?;
+      w.flush()?;
+    }
+
+    let len = fs::metadata(local_path)?.len();
+    if len != size {
+      return Err(AdbError::Fail(format!(
+        "failed to resume '{}': got {} of {} bytes",
+        remote_path, len, size
+      )));
+    }
+
+    let (mut r, hashes) = HashingReader::new(File::open(local_path)?);
+    io::copy(&mut r, &mut io::sink())?;
+    verify(self, remote_path, &hashes.borrow())?;
+
+    set_mtime(local_path, &metadata)?;
+
+    Ok(fetched)
+  }
+
+  fn pull_dir<P: AsRef<Path>>(
+    &mut self,
+    remote_dir: &str,
+    local_dir: P,
+    options: &PullOptions,
+  ) -> AdbResult<PullDirSummary> {
+    let remote_dir = remote_dir.trim_end_matches('/');
+
+    let follow_symlinks = options.follow_symlinks;
+    let session = SyncSession::open(self)?;
+    let metadata = if follow_symlinks {
+      stat_follow(self, &session, remote_dir)?
+    } else {
+      session.stat(remote_dir)?
+    };
+    if !metadata.exists() {
+      session.quit()?;
+      return Err(not_found(remote_dir, &metadata));
+    }
+    if !metadata.is_dir() {
+      session.quit()?;
+      return Err(AdbError::Fail(format!(
+        "remote object '{}' is not a directory",
+        remote_dir
+      )));
+    }
+
+    let mut puller = DirPuller {
+      conn: self,
+      session: Some(session),
+      follow_symlinks,
+      ancestors: vec![],
+      files: vec![],
+      dirs: vec![],
+      failed: vec![],
+      failed_dirs: vec![],
+    };
+    puller.pull_dir(remote_dir, local_dir.as_ref(), &metadata);
+
+    Ok(puller.transfer(options))
+  }
+
+  fn pull_glob<P: AsRef<Path>>(
+    &mut self,
+    pattern: &str,
+    local_dir: P,
+    options: &PullOptions,
+  ) -> AdbResult<PullDirSummary> {
+    let local_dir = local_dir.as_ref();
+
+    let follow_symlinks = options.follow_symlinks;
+    let session = SyncSession::open(self)?;
+    let matches = expand_remote(&session, pattern)?;
+    fs::create_dir_all(local_dir)?;
+
+    let mut puller = DirPuller {
+      conn: self,
+      session: Some(session),
+      follow_symlinks,
+      ancestors: vec![],
+      files: vec![],
+      dirs: vec![],
+      failed: vec![],
+      failed_dirs: vec![],
+    };
+    for entry in matches {
+      puller.pull_entry(
+        entry.path().to_string(),
+        local_dir.join(entry.name()),
+        *entry.metadata(),
+      );
+    }
+
+    Ok(puller.transfer(options))
+  }
+}
+
+pub(crate) fn not_found(remote_path: &str, metadata: &RemoteMetadata) -> AdbError {
+  AdbError::Fail(match metadata.error_message() {
+    Some(msg) => format!("failed to stat remote object '{}': {}", remote_path, msg),
+    None => format!("remote object '{}' does not exist", remote_path),
+  })
+}
+
+/// Walks remote directories, creating the local ones, then pulls the files
+/// and symlinks found across as many sync streams as the options' concurrency
+/// allows.
+struct DirPuller<'a> {
+  conn: &'a AdbConnection,
+  session: Option<SyncSession>,
+  follow_symlinks: bool,
+  // (dev, ino) of the directories being pulled, to detect symlink loops
+  ancestors: Vec<(u64, u64)>,
+  // (remote_path, local_path, metadata) of the files and symlinks to pull
+  files: Vec<(String, PathBuf, RemoteMetadata)>,
+  // in post-order, their metadata is applied once the files are pulled
+  dirs: Vec<(String, PathBuf, RemoteMetadata)>,
+  // entries that failed before being transferred
+  failed: Vec<PullFileResult>,
+  failed_dirs: Vec<PullDirError>,
+}
+
+impl<'a> DirPuller<'a> {
+  fn session(&mut self) -> AdbResult<&SyncSession> {
+    SyncSession::ensure_open(&mut self.session, self.conn).map(|session| &*session)
+  }
+
+  fn pull_dir(&mut self, remote_dir: &str, local_dir: &Path, metadata: &RemoteMetadata) {
+    let entries = fs::create_dir_all(local_dir)
+      .map_err(Into::into)
+      .and_then(|_| self.session()?.list(remote_dir));
+    let entries = match entries {
+      Ok(entries) => entries,
+      Err(error) => {
+        debug!("pull failed: {}: {}", remote_dir, error);
+        self.failed_dirs.push(PullDirError {
+          remote_path: remote_dir.to_string(),
+          local_path: local_dir.to_path_buf(),
+          error,
+        });
+        return;
+      }
+    };
+
+    // symlink loops are detected by inode, which is only listed on devices
+    // advertising `stat_v2` and `ls_v2`; elsewhere it takes a stat, only
+    // worth it if symlinks are followed
+    let mut ancestor = (metadata.dev(), metadata.ino());
+    if ancestor.1 == 0 && self.follow_symlinks {
+      let conn = self.conn;
+      let dir = self
+        .session()
+        .and_then(|session| stat_follow(conn, session, remote_dir));
+      if let Ok(dir) = dir {
+        ancestor = (dir.dev(), dir.ino());
+      }
+    }
+    if ancestor.1 != 0 {
+      self.ancestors.push(ancestor);
+    }
+
+    for entry in entries {
+      self.pull_entry(
+        format!("{}/{}", remote_dir, entry.name()),
+        local_dir.join(entry.name()),
+        *entry.metadata(),
+      );
+    }
+
+    if ancestor.1 != 0 {
+      self.ancestors.pop();
+    }
+
+    self
+      .dirs
+      .push((remote_dir.to_string(), local_dir.to_path_buf(), *metadata));
+  }
+
+  // `metadata` is that of the entry itself, symlinks are followed here
+  fn pull_entry(&mut self, remote_path: String, local_path: PathBuf, metadata: RemoteMetadata) {
+    let mut metadata = metadata;
+
+    if metadata.is_symlink() && self.follow_symlinks {
+      // dangling symlinks are recreated as is
+      let conn = self.conn;
+      match self
+        .session()
+        .and_then(|session| stat_follow(conn, session, &remote_path))
+      {
+        Ok(target) => {
+          if target.exists() {
+            metadata = target;
+          }
+        }
+        Err(err) => {
+          debug!("pull failed: {}: {}", remote_path, err);
+          self.failed.push(PullFileResult {
+            remote_path,
+            local_path,
+            result: Err(err),
+          });
+          return;
+        }
+      }
+    }
+
+    if metadata.is_dir() {
+      if self.ancestors.contains(&(metadata.dev(), metadata.ino())) {
+        debug!("pull: skipping symlink loop {}", remote_path);
+        return;
+      }
+      return self.pull_dir(&remote_path, &local_path, &metadata);
+    }
+
+    if metadata.is_file() || metadata.is_symlink() {
+      self.files.push((remote_path, local_path, metadata));
+    } else {
+      debug!("pull: skipping special file {}", remote_path);
+    }
+  }
+
+  fn transfer(mut self, options: &PullOptions) -> PullDirSummary {
+    // only used to walk the directories, failing to end it fails nothing
+    if let Some(session) = self.session.take() {
+      if let Err(err) = session.quit() {
+        debug!("pull: failed to end the sync session: {}", err);
+      }
+    }
+
+    let conn = self.conn;
+    let total = self
+      .files
+      .iter()
+      .filter(|(_, _, metadata)| metadata.is_file())
+      .map(|(_, _, metadata)| metadata.len())
+      .sum();
+    let results = transfer_all(
+      conn,
+      self.files,
+      Some(total),
+      options.concurrency,
+      options.compression,
+      |session, (remote_path, local_path, metadata)| {
+        let result = if metadata.is_file() {
+          pull_file(session, remote_path, local_path, metadata, options)
+        } else {
+          pull_symlink(conn, remote_path, local_path)
+        };
+
+        if let Err(ref err) = result {
+          debug!("pull failed: {}: {}", remote_path, err);
+        }
+
+        result
+      },
+    );
+
+    let mut failed_dirs = self.failed_dirs;
+    for (remote_dir, local_dir, metadata) in self.dirs {
+      if let Err(error) = apply_metadata(options, &local_dir, &metadata) {
+        debug!("pull failed: {}: {}", remote_dir, error);
+        failed_dirs.push(PullDirError {
+          remote_path: remote_dir,
+          local_path: local_dir,
+          error,
+        });
+      }
+    }
+
+    let mut files = self.failed;
+    files.extend(
+      results
+        .into_iter()
+        .map(|((remote_path, local_path, _), result)| PullFileResult {
+          remote_path,
+          local_path,
+          result,
+        }),
+    );
+    PullDirSummary { files, failed_dirs }
+  }
+}
+
+fn pull_file(
+  session: &SyncSession,
+  remote_path: &str,
+  local_path: &Path,
+  metadata: &RemoteMetadata,
+  options: &PullOptions,
+) -> AdbResult<u64> {
+  debug!("pull: {} -> {}", remote_path, local_path.display());
+  let (_, n) = recv_file(session, remote_path, local_path, Some(metadata.len()))?;
+
+  apply_metadata(options, local_path, metadata)?;
+
+  Ok(n)
+}
+
+/// Receives `remote_path` into a temporary file next to `local_path`, renamed
+/// over it once complete so that a failed pull leaves an existing file as is.
+fn recv_file(
+  session: &SyncSession,
+  remote_path: &str,
+  local_path: &Path,
+  total: Option<u64>,
+) -> AdbResult<(File, u64)> {
+  let name = local_path
+    .file_name()
+    .map(|name| name.to_string_lossy().into_owned())
+    .unwrap_or_default();
+  let temp_path = local_path.with_file_name(format!(".{}.{}.part", name, process::id()));
+
+  let result = File::create(&temp_path)
+    .map_err(Into::into)
+    .and_then(|file| {
+      let mut w = BufWriter::new(file);
+      let n = session.recv_with_total(remote_path, &mut w, total)?;
+      let file = w.into_inner().map_err(|err| err.into_error())?;
+      fs::rename(&temp_path, local_path)?;
+      Ok((file, n))
+    });
+  if result.is_err() {
+    fs::remove_file(&temp_path).ok();
+  }
+  result
+}
+
+fn pull_symlink(conn: &AdbConnection, remote_path: &str, local_path: &Path) -> AdbResult<u64> {
+  let target = readlink(conn, remote_path)?;
+  debug!(
+    "pull: {} -> {} (symlink to {})",
+    remote_path,
+    local_path.display(),
+    target
+  );
+
+  replace_with_symlink(&target, local_path)?;
+
+  Ok(0)
+}
+
+fn apply_metadata(
+  options: &PullOptions,
+  local_path: &Path,
+  metadata: &RemoteMetadata,
+) -> AdbResult<()> {
+  // the mtime is set first, the new mode may not allow opening the file
+  if options.preserve_mtime {
+    set_mtime(local_path, metadata)?;
+  }
+  if options.preserve_mode {
+    set_mode(local_path, metadata.permissions())?;
+  }
+  Ok(())
+}
+
+/// `SyncSession::stat_follow`, falling back to `stat -L` through `exec:` on
+/// devices without `stat_v2`, whose `STAT` does not follow symlinks.
+fn stat_follow(
+  conn: &AdbConnection,
+  session: &SyncSession,
+  remote_path: &str,
+) -> AdbResult<RemoteMetadata> {
+  if conn.has_feature("stat_v2") {
+    session.stat_follow(remote_path)
+  } else {
+    stat_follow_exec(conn, remote_path)
+  }
+}
+
+/// Reads the target of a remote symlink with `readlink`.
+pub(crate) fn readlink(conn: &AdbConnection, remote_path: &str) -> AdbResult<String> {
+  let cmd = ShellCommand::new("readlink").arg("--").arg(remote_path);
+  let (target, status) = exec_status(conn, &format!("{} 2>/dev/null", cmd.command_line()))?;
+  if status != 0 || target.is_empty() {
+    return Err(AdbError::Fail(format!(
+      "failed to read link '{}'",
+      remote_path
+    )));
+  }
+  Ok(target)
+}
+
+/// Creates a local symlink, replacing an existing file.
+fn replace_with_symlink(target: &str, local_path: &Path) -> AdbResult<()> {
+  if fs::symlink_metadata(local_path).is_ok() {
+    fs::remove_file(local_path)?;
+  }
+  symlink(target, local_path)
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, local_path: &Path) -> AdbResult<()> {
+  ::std::os::unix::fs::symlink(target, local_path).map_err(Into::into)
+}
+
+#[cfg(not(unix))]
+fn symlink(target: &str, local_path: &Path) -> AdbResult<()> {
+  Err(AdbError::Fail(format!(
+    "cannot create symlink '{}' -> '{}': not supported on this platform",
+    local_path.display(),
+    target
+  )))
+}
+
+// setting times needs write access on Windows, where opening a directory
+// also needs `FILE_FLAG_BACKUP_SEMANTICS`
+#[cfg(windows)]
+fn set_mtime(local_path: &Path, metadata: &RemoteMetadata) -> AdbResult<()> {
+  use std::os::windows::fs::OpenOptionsExt;
+  const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+  fs::OpenOptions::new()
+    .write(true)
+    .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+    .open(local_path)?
+    .set_modified(metadata.modified())
+    .map_err(Into::into)
+}
+
+// any handle will do, but directories cannot be opened for writing
+#[cfg(not(windows))]
+fn set_mtime(local_path: &Path, metadata: &RemoteMetadata) -> AdbResult<()> {
+  File::open(local_path)?
+    .set_modified(metadata.modified())
+    .map_err(Into::into)
+}
+
+#[cfg(unix)]
+fn set_mode(local_path: &Path, mode: u32) -> AdbResult<()> {
+  use std::os::unix::fs::PermissionsExt;
+  fs::set_permissions(local_path, fs::Permissions::from_mode(mode)).map_err(Into::into)
+}
+
+#[cfg(not(unix))]
+fn set_mode(_local_path: &Path, _mode: u32) -> AdbResult<()> {
+  Ok(())
+}
+
+/// Receives a file with `RECV`, or `RCV2` if it is to be compressed with
+/// `codec`, returning the number of bytes received.
+pub(crate) fn recv<W: Write>(
+  stream: &AdbStream,
+  remote_path: &str,
+  w: W,
+  codec: Option<Codec>,
+  progress: &mut Progress,
+) -> AdbResult<u64> {
+  let mut w = w;
+
+  let recv = match codec {
+    Some(codec) => {
+      debug!("RCV2 {:?}", codec);
+      SyncCommand::new_recv_v2(remote_path, codec.flag())
+    }
+    None => {
+      debug!("RECV");
+      SyncCommand::new_recv(remote_path)
+    }
+  };
+  let mut reader = SyncReader::new(stream);
+  stream.send(AdbStreamPacket::new_write(recv))?;
+  reader.wait_ok()?;
+
+  let mut records = SyncDataReader::new(reader);
+  let result = match codec {
+    Some(codec) => decoder(codec, &mut records).and_then(|r| copy(r, &mut w, progress)),
+    None => copy(&mut records, &mut w, progress),
+  };
+  let bytes_received = result.map_err(|err| records.take_error().unwrap_or(err))?;
+  records.finish()?;
+
+  debug!("RECV done: 0x{:x} bytes", bytes_received);
+
+  Ok(bytes_received)
+}
+
+fn copy<R: Read, W: Write>(r: R, w: W, progress: &mut Progress) -> AdbResult<u64> {
+  let mut r = r;
+  let mut w = w;
+  let mut buf = vec![0; SYNC_DATA_MAX];
+  let mut total: u64 = 0;
+
+  loop {
+    let n = match r.read(&mut buf) {
+      Ok(0) => break,
+      Ok(n) => n,
+      Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+      Err(err) => return Err(err.into()),
+    };
+    w.write_all(&buf[..n])?;
+    total += n as u64;
+    progress.advance(n as u64);
+  }
+
+  w.flush()?;
+
+  Ok(total)
+}
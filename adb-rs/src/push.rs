@@ -1,128 +1,547 @@
-use std::fs::File;
+use bytes::BytesMut;
+use std::cell::Cell;
+use std::fs::{self, File};
 use std::io::prelude::*;
-use std::io::BufReader;
-use std::io::SeekFrom;
-use std::path::Path;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::time::SystemTime;
 
 use super::client::*;
+use super::compression::{encoder, Codec, Compression};
+use super::glob::expand_local;
+use super::parallel::transfer_all;
+use super::progress::Progress;
+use super::session::SyncSession;
+use super::stat::S_IFREG;
 use super::sync::*;
+use super::verify::{verify, HashingReader};
 use crate::result::*;
 
+/// Attributes of pushed files and how they are transferred.
+#[derive(Debug, Clone)]
+pub struct PushOptions {
+  /// Permission bits of the remote files. If unset, those of the local
+  /// files, or `0o644` for `push_reader`.
+  pub mode: Option<u32>,
+  /// Modification time of the remote files. If unset, that of the local
+  /// files, or the current time for `push_reader`.
+  pub mtime: Option<SystemTime>,
+  /// Pushes the files symlinks point to, instead of recreating the symlinks
+  /// themselves.
+  pub follow_symlinks: bool,
+  /// Compares every pushed file with a hash computed on the device, failing
+  /// with `AdbError::ChecksumMismatch` if they differ.
+  pub verify: bool,
+  pub compression: Compression,
+  /// How many sync streams `push_dir` and `push_glob` spread files across,
+  /// each transferring one file at a time.
+  pub concurrency: usize,
+}
+
+impl Default for PushOptions {
+  fn default() -> Self {
+    PushOptions {
+      mode: None,
+      mtime: None,
+      follow_symlinks: false,
+      verify: false,
+      compression: Compression::None,
+      concurrency: 1,
+    }
+  }
+}
+
+impl PushOptions {
+  /// Takes the permission bits and mtime of a local file.
+  pub fn from_metadata(metadata: &fs::Metadata) -> PushOptions {
+    PushOptions {
+      mode: Some(local_mode(metadata)),
+      mtime: metadata.modified().ok(),
+      ..Default::default()
+    }
+  }
+
+  // these options, taking the attributes left unset from a local file
+  fn with_metadata(&self, metadata: &fs::Metadata) -> PushOptions {
+    PushOptions {
+      mode: self.mode.or_else(|| Some(local_mode(metadata))),
+      mtime: self.mtime.or_else(|| metadata.modified().ok()),
+      ..self.clone()
+    }
+  }
+
+  pub(crate) fn remote_mode(&self) -> u32 {
+    S_IFREG | (self.mode.unwrap_or(0o644) & 0o7777)
+  }
+
+  pub(crate) fn remote_mtime(&self) -> u32 {
+    self
+      .mtime
+      .unwrap_or_else(SystemTime::now)
+      .duration_since(SystemTime::UNIX_EPOCH)
+      .map(|d| d.as_secs() as u32)
+      .unwrap_or(0)
+  }
+}
+
+#[cfg(unix)]
+fn local_mode(metadata: &fs::Metadata) -> u32 {
+  use std::os::unix::fs::PermissionsExt;
+  metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn local_mode(metadata: &fs::Metadata) -> u32 {
+  if metadata.permissions().readonly() {
+    0o444
+  } else {
+    0o644
+  }
+}
+
+// (dev, ino) of a local file, to detect symlink loops
+#[cfg(unix)]
+pub(crate) fn local_file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+  use std::os::unix::fs::MetadataExt;
+  Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn local_file_id(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+  None
+}
+
 pub trait AdbPush {
-  fn push_reader<R: Read + Seek>(&mut self, r: R, remote_path: &str) -> AdbResult<()>;
-  fn push<P: AsRef<Path>>(&mut self, local_path: P, remote_path: &str) -> AdbResult<()>;
+  fn push_reader<R: Read>(
+    &mut self,
+    r: R,
+    remote_path: &str,
+    options: &PushOptions,
+  ) -> AdbResult<()>;
+  /// Pushes a local file, keeping its permission bits and mtime unless
+  /// `options` sets them.
+  ///
+  /// A symlink is recreated on the device, unless `options` follows
+  /// symlinks.
+  fn push<P: AsRef<Path>>(
+    &mut self,
+    local_path: P,
+    remote_path: &str,
+    options: &PushOptions,
+  ) -> AdbResult<()>;
+  /// Pushes every file under `local_dir` to `remote_dir` over one sync
+  /// stream, preserving the relative layout, permission bits and mtimes.
+  /// Files are spread across several streams if `options.concurrency` is
+  /// above 1.
+  ///
+  /// Remote directories are created implicitly by `SEND`, so empty local
+  /// directories are not recreated. Symlinks are recreated, unless `options`
+  /// follows symlinks. A failing file does not abort the transfer; its error
+  /// is reported in the returned summary.
+  fn push_dir<P: AsRef<Path>>(
+    &mut self,
+    local_dir: P,
+    remote_dir: &str,
+    options: &PushOptions,
+  ) -> AdbResult<PushDirSummary>;
+  /// Pushes the local paths matching `pattern` into `remote_dir` over one
+  /// sync stream, or several as with `push_dir`; see `glob` for the syntax.
+  ///
+  /// Matching directories are pushed recursively, as with `push_dir`. Fails
+  /// if nothing matches.
+  fn push_glob(
+    &mut self,
+    pattern: &str,
+    remote_dir: &str,
+    options: &PushOptions,
+  ) -> AdbResult<PushDirSummary>;
+}
+
+/// Outcome of pushing a single file during `push_dir` or `push_glob`.
+#[derive(Debug)]
+pub struct PushFileResult {
+  pub local_path: PathBuf,
+  pub remote_path: String,
+  /// Number of bytes sent.
+  pub result: AdbResult<u64>,
+}
+
+/// Per-file results and totals of a `push_dir` or `push_glob`.
+#[derive(Debug, Default)]
+pub struct PushDirSummary {
+  pub files: Vec<PushFileResult>,
+}
+
+impl PushDirSummary {
+  pub fn total_files(&self) -> usize {
+    self.files.len()
+  }
+
+  pub fn total_bytes(&self) -> u64 {
+    self
+      .files
+      .iter()
+      .filter_map(|f| f.result.as_ref().ok())
+      .sum()
+  }
+
+  pub fn failed(&self) -> impl Iterator<Item = &PushFileResult> {
+    self.files.iter().filter(|f| f.result.is_err())
+  }
+
+  pub fn is_success(&self) -> bool {
+    self.failed().next().is_none()
+  }
 }
 
 impl AdbPush for AdbConnection {
-  fn push_reader<R: Read + Seek>(&mut self, r: R, remote_path: &str) -> AdbResult<()> {
-    let mut r = r;
+  fn push_reader<R: Read>(
+    &mut self,
+    r: R,
+    remote_path: &str,
+    options: &PushOptions,
+  ) -> AdbResult<()> {
+    let mut session = SyncSession::open(self)?;
+    session.set_compression(options.compression);
 
-    let size = r.seek(SeekFrom::End(0))?;
-    r.seek(SeekFrom::Start(0))?;
+    session.stat(remote_path)?;
 
-    debug!("size = 0x{:x}", size);
+    send_verified(self, &session, r, remote_path, options, None)?;
 
-    let stream = self.open_stream("sync:")?;
+    session.quit()
+  }
 
-    debug!("STAT");
-    let stat = SyncCommand::new_stat(remote_path);
-    let packet = AdbStreamPacket::new_write(stat);
-    stream.send(packet)?;
-    stream.sync_recv_ok()?;
-    debug!("STAT ok");
+  fn push<P: AsRef<Path>>(
+    &mut self,
+    local_path: P,
+    remote_path: &str,
+    options: &PushOptions,
+  ) -> AdbResult<()> {
+    let mut session = SyncSession::open(self)?;
+    session.set_compression(options.compression);
 
-    let reply = stream.sync_recv_command(Command::A_WRTE)?;
-    debug!(
-      "STAT data received: payload len = 0x{:x}",
-      reply.payload.len()
-    );
+    session.stat(remote_path)?;
 
-    stream.send_ok()?;
+    push_file(self, &session, local_path.as_ref(), remote_path, options)?;
 
-    debug!("SEND");
-    let send = SyncCommand::new_send(remote_path, 0o100644);
-    let packet = AdbStreamPacket::new_write(send);
-    stream.send(packet)?;
-    stream.sync_recv_ok()?;
-    debug!("SEND ok");
+    session.quit()
+  }
 
-    let mut data = SyncCommand::new_data(self.max_data_len());
-    let mut bytes_sent = 0;
+  fn push_dir<P: AsRef<Path>>(
+    &mut self,
+    local_dir: P,
+    remote_dir: &str,
+    options: &PushOptions,
+  ) -> AdbResult<PushDirSummary> {
+    let mut collector = FileCollector::new(options.follow_symlinks);
+    collector.collect_dir(local_dir.as_ref(), remote_dir.trim_end_matches('/'))?;
 
-    loop {
-      let n = data.read_payload_from(&mut r)?;
-      if n == 0 {
-        break;
-      }
+    push_files(self, collector.files, options)
+  }
 
-      let next_pos: u64 = bytes_sent + n as u64;
-
-      assert!(next_pos <= size);
-
-      let is_last_chunk = next_pos == size;
-      debug!("DATA [0x{:x}:0x{:x}]", bytes_sent, next_pos);
-      bytes_sent = bytes_sent + n as u64;
-
-      let packet = AdbStreamPacket::new_write(&data);
-
-      if is_last_chunk {
-        let now = SystemTime::now()
-          .duration_since(SystemTime::UNIX_EPOCH)
-          .unwrap()
-          .as_secs() as u32;
-        let done = SyncCommand::new_done(now);
-        let space = self.max_data_len() - data.len();
-        if space >= done.len() {
-          data.extend(done);
-          let packet = AdbStreamPacket::new_write(&data);
-          stream.send(packet)?;
-          stream.sync_recv_ok()?;
-          debug!("DATA last chunk ok");
-        } else {
-          let append_len = space;
-          if append_len > 0 {
-            data.extend(&done[0..append_len]);
-          }
-          let packet = AdbStreamPacket::new_write(&data);
-          stream.send(packet)?;
-          stream.sync_recv_ok()?;
-          stream.send(AdbStreamPacket::new_write(&done[append_len..]))?;
-          stream.sync_recv_ok()?;
-        }
-        break;
+  fn push_glob(
+    &mut self,
+    pattern: &str,
+    remote_dir: &str,
+    options: &PushOptions,
+  ) -> AdbResult<PushDirSummary> {
+    let remote_dir = remote_dir.trim_end_matches('/');
+    let mut collector = FileCollector::new(options.follow_symlinks);
+
+    for local_path in expand_local(pattern)? {
+      let name = match local_path.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => continue,
+      };
+      let remote_path = format!("{}/{}", remote_dir, name);
+      collector.collect(local_path, remote_path)?;
+    }
+
+    push_files(self, collector.files, options)
+  }
+}
+
+/// Pushes `(local_path, remote_path)` pairs, spread across as many sync
+/// streams as `options.concurrency` allows.
+fn push_files(
+  conn: &AdbConnection,
+  files: Vec<(PathBuf, String)>,
+  options: &PushOptions,
+) -> AdbResult<PushDirSummary> {
+  let total = files
+    .iter()
+    .filter_map(|(local_path, _)| {
+      if options.follow_symlinks {
+        fs::metadata(local_path).ok()
       } else {
-        stream.send(packet)?;
+        fs::symlink_metadata(local_path).ok()
+      }
+    })
+    .filter(|metadata| metadata.is_file())
+    .map(|metadata| metadata.len())
+    .sum();
+
+  let results = transfer_all(
+    conn,
+    files,
+    Some(total),
+    options.concurrency,
+    options.compression,
+    |session, (local_path, remote_path)| {
+      debug!("push: {} -> {}", local_path.display(), remote_path);
+      let result = push_file(conn, session, local_path, remote_path, options);
 
-        stream.sync_recv_ok()?;
-        debug!("DATA chunk ok");
+      if let Err(ref err) = result {
+        debug!("push failed: {}: {}", local_path.display(), err);
       }
-    }
 
-    assert_eq!(bytes_sent as u64, size);
+      result
+    },
+  );
 
-    let reply = stream.sync_recv_command(Command::A_WRTE)?;
-    debug!("result = {}", String::from_utf8_lossy(&reply.payload));
+  Ok(PushDirSummary {
+    files: results
+      .into_iter()
+      .map(|((local_path, remote_path), result)| PushFileResult {
+        local_path,
+        remote_path,
+        result,
+      })
+      .collect(),
+  })
+}
 
-    stream.send_ok()?;
+/// Sends a local file over `session`, keeping its permission bits and mtime
+/// unless `options` sets them.
+///
+/// A symlink is recreated on the device unless `options` follows symlinks,
+/// in which case the file it points to is sent.
+pub(crate) fn push_file(
+  conn: &AdbConnection,
+  session: &SyncSession,
+  local_path: &Path,
+  remote_path: &str,
+  options: &PushOptions,
+) -> AdbResult<u64> {
+  if !options.follow_symlinks && fs::symlink_metadata(local_path)?.file_type().is_symlink() {
+    let target = local_link_target(local_path)?;
+    debug!("push: {} is a symlink to {}", local_path.display(), target);
+    session.symlink(&target, remote_path)?;
+    return Ok(0);
+  }
 
-    debug!("QUIT");
-    let quit = SyncCommand::new_quit();
-    let packet = AdbStreamPacket::new_write(quit);
-    stream.send(packet)?;
+  let file = File::open(local_path)?;
+  let metadata = file.metadata()?;
+  let options = options.with_metadata(&metadata);
+  send_verified(
+    conn,
+    session,
+    BufReader::new(file),
+    remote_path,
+    &options,
+    Some(metadata.len()),
+  )
+}
+
+/// Sends `r` over `session`, then compares it with the remote file if
+/// `options` verifies pushes.
+fn send_verified<R: Read>(
+  conn: &AdbConnection,
+  session: &SyncSession,
+  r: R,
+  remote_path: &str,
+  options: &PushOptions,
+  total: Option<u64>,
+) -> AdbResult<u64> {
+  if !options.verify {
+    return session.send_with_total(r, remote_path, options, total);
+  }
 
-    stream.sync_recv_ok()?;
+  let (r, hashes) = HashingReader::new(r);
+  let n = session.send_with_total(r, remote_path, options, total)?;
+  verify(conn, remote_path, &hashes.borrow())?;
+  Ok(n)
+}
 
-    stream.send_close()?;
+/// Counts the bytes read through it.
+struct CountingReader<R> {
+  inner: R,
+  count: Rc<Cell<u64>>,
+}
 
-    stream.sync_recv_command(Command::A_CLSE)?;
+impl<R: Read> Read for CountingReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let n = self.inner.read(buf)?;
+    self.count.set(self.count.get() + n as u64);
+    Ok(n)
+  }
+}
+
+/// Reads the target of a local symlink.
+pub(crate) fn local_link_target(local_path: &Path) -> AdbResult<String> {
+  let target = fs::read_link(local_path)?;
+  match target.to_str() {
+    Some(target) => Ok(target.to_string()),
+    None => Err(AdbError::Fail(format!(
+      "symlink target of '{}' is not valid UTF-8",
+      local_path.display()
+    ))),
+  }
+}
+
+/// Walks local directories, collecting the files and symlinks to push.
+struct FileCollector {
+  follow_symlinks: bool,
+  // (dev, ino) of the directories being walked, to detect symlink loops
+  ancestors: Vec<(u64, u64)>,
+  // (local_path, remote_path) of the files and symlinks to push
+  files: Vec<(PathBuf, String)>,
+}
+
+impl FileCollector {
+  fn new(follow_symlinks: bool) -> Self {
+    FileCollector {
+      follow_symlinks,
+      ancestors: vec![],
+      files: vec![],
+    }
+  }
+
+  fn collect_dir(&mut self, local_dir: &Path, remote_dir: &str) -> AdbResult<()> {
+    let id = local_file_id(&fs::metadata(local_dir)?);
+    let mut entries = fs::read_dir(local_dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    self.ancestors.extend(id);
+    for entry in entries {
+      let remote_path = format!("{}/{}", remote_dir, entry.file_name().to_string_lossy());
+      self.collect(entry.path(), remote_path)?;
+    }
+    if id.is_some() {
+      self.ancestors.pop();
+    }
 
     Ok(())
   }
 
-  fn push<P: AsRef<Path>>(&mut self, local_path: P, remote_path: &str) -> AdbResult<()> {
-    let file = File::open(local_path)?;
-    let r = BufReader::new(file);
-    self.push_reader(r, remote_path)
+  // `local_path` is a directory entry, or a path matching a pattern
+  fn collect(&mut self, local_path: PathBuf, remote_path: String) -> AdbResult<()> {
+    let metadata = if self.follow_symlinks {
+      fs::metadata(&local_path)
+    } else {
+      fs::symlink_metadata(&local_path)
+    };
+    match metadata {
+      Ok(ref metadata) if metadata.is_dir() => {
+        if local_file_id(metadata).is_some_and(|id| self.ancestors.contains(&id)) {
+          debug!("push: skipping symlink loop {}", local_path.display());
+          return Ok(());
+        }
+        self.collect_dir(&local_path, &remote_path)
+      }
+      // a dangling symlink fails to push, with its own error
+      _ => {
+        self.files.push((local_path, remote_path));
+        Ok(())
+      }
+    }
   }
 }
+
+/// Sends the contents of a reader with `SEND`, or `SND2` if it is to be
+/// compressed with `codec`, until EOF and waits for the device's reply.
+///
+/// `mode` includes the file type: for `S_IFLNK`, the contents are the target
+/// of the symlink to create.
+///
+/// Returns the number of bytes read from `r`.
+pub(crate) fn send<R: Read>(
+  stream: &AdbStream,
+  r: R,
+  remote_path: &str,
+  mode: u32,
+  mtime: u32,
+  codec: Option<Codec>,
+  progress: &mut Progress,
+) -> AdbResult<u64> {
+  let send = match codec {
+    Some(codec) => {
+      debug!("SND2 {:?}", codec);
+      SyncCommand::new_send_v2(remote_path, mode, codec.flag())
+    }
+    None => {
+      debug!("SEND");
+      SyncCommand::new_send(remote_path, mode)
+    }
+  };
+  let mut reader = SyncReader::new(stream);
+  let packet = AdbStreamPacket::new_write(send);
+  stream.send(packet)?;
+  reader.wait_ok()?;
+  debug!("SEND ok");
+
+  // progress is reported in bytes read from `r`, before compression
+  let bytes_read = Rc::new(Cell::new(0));
+  let r = CountingReader {
+    inner: r,
+    count: bytes_read.clone(),
+  };
+  let mut r: Box<dyn Read> = match codec {
+    Some(codec) => encoder(codec, r)?,
+    None => Box::new(r),
+  };
+
+  let max_data_len = stream.max_data_len();
+  let done = SyncCommand::new_done(mtime);
+  let mut bytes_sent: u64 = 0;
+
+  // DATA records are packed into as few adb packets as possible. The size is
+  // unknown upfront, so DONE follows the first short read, in the last packet
+  // if it fits.
+  let mut data = BytesMut::with_capacity(max_data_len);
+  let (_, mut eof) = read_data_records(&mut r, &mut data, max_data_len)?;
+  let mut pos = bytes_read.get();
+  loop {
+    let is_last = eof && data.len() + done.len() <= max_data_len;
+    if is_last {
+      data.extend_from_slice(&done);
+    }
+
+    debug!(
+      "DATA [0x{:x}:0x{:x}]{}",
+      bytes_sent,
+      pos,
+      if is_last { " DONE" } else { "" }
+    );
+    stream.send(AdbStreamPacket::new_write(&data))?;
+
+    // read the next packet from `r` while this one is in flight
+    let mut next = BytesMut::with_capacity(max_data_len);
+    let next_eof = if eof {
+      true
+    } else {
+      read_data_records(&mut r, &mut next, max_data_len)?.1
+    };
+
+    reader.wait_ok()?;
+    progress.advance(pos - bytes_sent);
+    bytes_sent = pos;
+
+    if is_last {
+      break;
+    }
+
+    data = next;
+    eof = next_eof;
+    pos = bytes_read.get();
+  }
+
+  match reader.read_record()? {
+    SyncRecord::Okay => {}
+    record => return Err(record.into_error()),
+  }
+  debug!("SEND done: 0x{:x} bytes", bytes_sent);
+
+  Ok(bytes_sent)
+}
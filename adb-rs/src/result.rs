@@ -23,8 +23,24 @@ pub enum AdbError {
   #[fail(display = "disconnected")]
   Disconnected,
 
+  #[fail(display = "timed out")]
+  Timeout,
+
+  #[fail(display = "cancelled")]
+  Cancelled,
+
   #[fail(display = "fail: {}", _0)]
   Fail(String),
+
+  #[fail(
+    display = "checksum mismatch: {}: expected {}, got {}",
+    path, expected, actual
+  )]
+  ChecksumMismatch {
+    path: String,
+    expected: String,
+    actual: String,
+  },
 }
 
 impl AdbError {
@@ -46,3 +62,16 @@ impl From<::std::io::Error> for AdbError {
     AdbError::Io(err)
   }
 }
+
+impl From<AdbError> for ::std::io::Error {
+  fn from(err: AdbError) -> ::std::io::Error {
+    use failure::Fail;
+    use std::io::{Error, ErrorKind};
+    match err {
+      AdbError::Io(err) => err,
+      AdbError::Disconnected => Error::new(ErrorKind::BrokenPipe, err.compat()),
+      AdbError::Timeout => Error::new(ErrorKind::TimedOut, err.compat()),
+      err => Error::other(err.compat()),
+    }
+  }
+}
@@ -0,0 +1,385 @@
+use bytes::BytesMut;
+use std::cell::Cell;
+use std::io::{self, prelude::*};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use super::client::*;
+use super::compression::Compression;
+use super::list::{list, RemoteDirEntry};
+use super::progress::{Progress, TransferObserver};
+use super::pull::recv;
+use super::push::{send, PushOptions};
+use super::stat::{stat, RemoteMetadata, S_IFLNK};
+use super::sync::*;
+use crate::result::*;
+
+/// A `sync:` stream reused for any number of file operations.
+///
+/// Opening and tearing down a sync stream costs a few round trips, so batch
+/// operations should share one session. The session sends `QUIT` when
+/// dropped, or explicitly with `quit` to observe errors.
+///
+/// The device closes the stream after a failed operation. The session is
+/// then unusable and every further operation returns
+/// `AdbError::Disconnected`; open a new one to continue.
+pub struct SyncSession {
+  stream: Option<AdbStream>,
+  stat_v2: bool,
+  ls_v2: bool,
+  failed: Cell<bool>,
+  observer: Option<Arc<dyn TransferObserver>>,
+  features: Vec<String>,
+  compression: Compression,
+}
+
+impl SyncSession {
+  /// Opens a sync stream, using the `stat_v2` and `ls_v2` requests if the
+  /// device advertises them. Transfers report to the connection's observer
+  /// and are not compressed, see `set_compression`.
+  pub fn open(conn: &AdbConnection) -> AdbResult<SyncSession> {
+    let stream = conn.open_stream("sync:")?;
+    Ok(SyncSession {
+      stream: Some(stream),
+      stat_v2: conn.has_feature("stat_v2"),
+      ls_v2: conn.has_feature("ls_v2"),
+      failed: Cell::new(false),
+      observer: conn.transfer_observer().cloned(),
+      features: conn.device_features().to_vec(),
+      compression: Compression::None,
+    })
+  }
+
+  /// Returns the session in `session`, first opening a new one if there is
+  /// none or it failed.
+  ///
+  /// A failed session is dropped before the new one is opened: packets the
+  /// device still sends to it would otherwise fill its queue and stall the
+  /// connection's other streams, including the new one's `A_OPEN`.
+  pub fn ensure_open<'s>(
+    session: &'s mut Option<SyncSession>,
+    conn: &AdbConnection,
+  ) -> AdbResult<&'s mut SyncSession> {
+    if session.as_ref().is_some_and(SyncSession::is_failed) {
+      drop(session.take());
+    }
+    if session.is_none() {
+      *session = Some(SyncSession::open(conn)?);
+    }
+    Ok(session.as_mut().unwrap())
+  }
+
+  /// Sets the compression of `send` and `recv`.
+  pub fn set_compression(&mut self, compression: Compression) {
+    self.compression = compression;
+  }
+
+  /// Sets the observer notified of the progress of `send` and `recv`.
+  pub fn set_observer(&mut self, observer: Option<Arc<dyn TransferObserver>>) {
+    self.observer = observer;
+  }
+
+  /// Returns the metadata of `remote_path`, without following symlinks.
+  pub fn stat(&self, remote_path: &str) -> AdbResult<RemoteMetadata> {
+    let stat_v2 = self.stat_v2;
+    self.run(|stream| stat(stream, remote_path, stat_v2, false))
+  }
+
+  /// Returns the metadata of `remote_path`, following symlinks.
+  ///
+  /// Devices without `stat_v2` cannot follow symlinks; the metadata of the
+  /// symlink itself is returned instead.
+  pub fn stat_follow(&self, remote_path: &str) -> AdbResult<RemoteMetadata> {
+    let stat_v2 = self.stat_v2;
+    self.run(|stream| stat(stream, remote_path, stat_v2, true))
+  }
+
+  /// Lists `remote_path`, skipping the `.` and `..` entries.
+  pub fn list(&self, remote_path: &str) -> AdbResult<Vec<RemoteDirEntry>> {
+    let ls_v2 = self.ls_v2;
+    self.run(|stream| list(stream, remote_path, ls_v2))
+  }
+
+  /// Sends the contents of `r` to `remote_path`, returning the number of
+  /// bytes sent.
+  ///
+  /// Only the mode and mtime of `options` apply: the session's compression
+  /// is used, and the file is not verified.
+  pub fn send<R: Read>(&self, r: R, remote_path: &str, options: &PushOptions) -> AdbResult<u64> {
+    self.send_with_total(r, remote_path, options, None)
+  }
+
+  /// Creates a symlink at `remote_path` pointing to `target`.
+  ///
+  /// An existing regular file is replaced, but adbd refuses to replace an
+  /// existing symlink.
+  pub fn symlink(&self, target: &str, remote_path: &str) -> AdbResult<()> {
+    // adbd neither decompresses symlink targets nor sets the mtime of
+    // symlinks, and the target is too short to be worth reporting
+    let mut progress = Progress::start(None, remote_path, None);
+    let mode = S_IFLNK | 0o777;
+    let mtime = PushOptions::default().remote_mtime();
+    self.run(|stream| {
+      send(
+        stream,
+        target.as_bytes(),
+        remote_path,
+        mode,
+        mtime,
+        None,
+        &mut progress,
+      )
+    })?;
+    Ok(())
+  }
+
+  /// Receives `remote_path` into `w`, returning the number of bytes received.
+  pub fn recv<W: Write>(&self, remote_path: &str, w: W) -> AdbResult<u64> {
+    self.recv_with_total(remote_path, w, None)
+  }
+
+  // `send`, reporting `total` as the size to the observer
+  pub(crate) fn send_with_total<R: Read>(
+    &self,
+    r: R,
+    remote_path: &str,
+    options: &PushOptions,
+    total: Option<u64>,
+  ) -> AdbResult<u64> {
+    let codec = self.compression.resolve(&self.features)?;
+    let mut progress = Progress::start(self.observer.as_deref(), remote_path, total);
+    let (mode, mtime) = (options.remote_mode(), options.remote_mtime());
+    let result = self.run(|stream| send(stream, r, remote_path, mode, mtime, codec, &mut progress));
+    progress.finish(&result);
+    result
+  }
+
+  // `recv`, reporting `total` as the size to the observer
+  pub(crate) fn recv_with_total<W: Write>(
+    &self,
+    remote_path: &str,
+    w: W,
+    total: Option<u64>,
+  ) -> AdbResult<u64> {
+    let codec = self.compression.resolve(&self.features)?;
+    let mut progress = Progress::start(self.observer.as_deref(), remote_path, total);
+    let result = self.run(|stream| recv(stream, remote_path, w, codec, &mut progress));
+    progress.finish(&result);
+    result
+  }
+
+  /// Starts receiving `remote_path`, returning a reader of its contents.
+  ///
+  /// The reader takes over the session, which ends with it: dropping the
+  /// reader sends `QUIT` once the whole file was read, and closes the stream
+  /// otherwise. The file is neither compressed nor reported to the observer.
+  pub fn into_recv(mut self, remote_path: &str) -> AdbResult<SyncRecvReader> {
+    let stream = self.take_stream()?;
+    debug!("RECV");
+    let mut reader = SyncReader::new(stream);
+    let result = reader
+      .stream()
+      .send(AdbStreamPacket::new_write(SyncCommand::new_recv(
+        remote_path,
+      )))
+      .and_then(|_| reader.wait_ok());
+    if let Err(err) = result {
+      let _ = reader.stream().send_close();
+      return Err(err);
+    }
+    Ok(SyncRecvReader {
+      records: SyncDataReader::new(reader),
+    })
+  }
+
+  /// Starts sending a file to `remote_path`, returning a writer of its
+  /// contents.
+  ///
+  /// Only the mode and mtime of `options` apply, as with `send`. The writer
+  /// takes over the session, which ends with it: the file is only committed
+  /// by `SyncSendWriter::finish`, dropping the writer unfinished closes the
+  /// stream and the device deletes the partial file. The file is neither
+  /// compressed nor reported to the observer.
+  pub fn into_send(
+    mut self,
+    remote_path: &str,
+    options: &PushOptions,
+  ) -> AdbResult<SyncSendWriter> {
+    let stream = self.take_stream()?;
+    debug!("SEND");
+    let max_len = stream.max_data_len();
+    let mut reader = SyncReader::new(stream);
+    let result = reader
+      .stream()
+      .send(AdbStreamPacket::new_write(SyncCommand::new_send(
+        remote_path,
+        options.remote_mode(),
+      )))
+      .and_then(|_| reader.wait_ok());
+    if let Err(err) = result {
+      let _ = reader.stream().send_close();
+      return Err(err);
+    }
+    Ok(SyncSendWriter {
+      reader,
+      buf: BytesMut::with_capacity(max_len),
+      max_len,
+      mtime: options.mtime,
+      finished: false,
+    })
+  }
+
+  /// Whether a previous operation failed and the session is unusable.
+  pub fn is_failed(&self) -> bool {
+    self.failed.get()
+  }
+
+  /// Ends the session with `QUIT`.
+  pub fn quit(mut self) -> AdbResult<()> {
+    self.close()
+  }
+
+  fn run<T, F>(&self, f: F) -> AdbResult<T>
+  where
+    F: FnOnce(&AdbStream) -> AdbResult<T>,
+  {
+    if self.failed.get() {
+      return Err(AdbError::Disconnected);
+    }
+    let stream = self.stream.as_ref().ok_or(AdbError::Disconnected)?;
+    f(stream).map_err(|err| {
+      debug!("sync session failed: {}", err);
+      self.failed.set(true);
+      err
+    })
+  }
+
+  // hands the stream over to a streaming transfer, which ends the session
+  fn take_stream(&mut self) -> AdbResult<AdbStream> {
+    if self.failed.get() {
+      return Err(AdbError::Disconnected);
+    }
+    self.stream.take().ok_or(AdbError::Disconnected)
+  }
+
+  fn close(&mut self) -> AdbResult<()> {
+    match self.stream.take() {
+      Some(stream) => {
+        if self.failed.get() {
+          stream.send_close()
+        } else {
+          stream.sync_quit()
+        }
+      }
+      None => Ok(()),
+    }
+  }
+}
+
+impl Drop for SyncSession {
+  fn drop(&mut self) {
+    self.close().ok();
+  }
+}
+
+/// A file being received, see `SyncSession::into_recv`.
+pub struct SyncRecvReader {
+  records: SyncDataReader<AdbStream>,
+}
+
+impl Read for SyncRecvReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.records.read(buf)
+  }
+}
+
+impl Drop for SyncRecvReader {
+  fn drop(&mut self) {
+    let stream = self.records.stream();
+    let _ = if self.records.is_done() {
+      stream.sync_quit()
+    } else {
+      stream.send_close()
+    };
+  }
+}
+
+/// A file being sent, see `SyncSession::into_send`.
+///
+/// What is written is sent as `DATA` records packed into full adb packets;
+/// `flush` sends a partial packet.
+pub struct SyncSendWriter {
+  reader: SyncReader<AdbStream>,
+  buf: BytesMut,
+  max_len: usize,
+  mtime: Option<SystemTime>,
+  finished: bool,
+}
+
+impl SyncSendWriter {
+  /// Sends the remaining data, waits for the device to commit the file and
+  /// ends the session.
+  pub fn finish(mut self) -> AdbResult<()> {
+    self.finished = true;
+    let result = self.send_done();
+    if result.is_err() {
+      let _ = self.reader.stream().send_close();
+    }
+    result
+  }
+
+  fn send_buffered(&mut self) -> AdbResult<()> {
+    if self.buf.is_empty() {
+      return Ok(());
+    }
+    let data = self.buf.take();
+    self
+      .reader
+      .stream()
+      .send(AdbStreamPacket::new_write(&data))?;
+    self.reader.wait_ok()
+  }
+
+  fn send_done(&mut self) -> AdbResult<()> {
+    let options = PushOptions {
+      mtime: self.mtime,
+      ..Default::default()
+    };
+    let done = SyncCommand::new_done(options.remote_mtime());
+    if self.buf.len() + done.len() > self.max_len {
+      self.send_buffered()?;
+    }
+    self.buf.extend_from_slice(&done);
+    self.send_buffered()?;
+
+    match self.reader.read_record()? {
+      SyncRecord::Okay => {}
+      record => return Err(record.into_error()),
+    }
+    debug!("SEND done");
+
+    self.reader.stream().sync_quit()
+  }
+}
+
+impl Write for SyncSendWriter {
+  fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+    if self.buf.len() + 8 >= self.max_len {
+      self.send_buffered()?;
+    }
+    let (n, _) = read_data_records(&mut &data[..], &mut self.buf, self.max_len)?;
+    Ok(n as usize)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.send_buffered().map_err(Into::into)
+  }
+}
+
+impl Drop for SyncSendWriter {
+  fn drop(&mut self) {
+    if !self.finished {
+      let _ = self.reader.stream().send_close();
+    }
+  }
+}
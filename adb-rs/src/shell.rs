@@ -1,31 +1,572 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::io::prelude::*;
+use std::io::{self, BufReader, Lines};
+use std::time::{Duration, Instant};
 
 use super::client::*;
 use crate::result::*;
 
+/// Runs commands through the `shell:` service.
+///
+/// On devices advertising `shell_v2`, commands run over shell protocol v2
+/// (`shell,v2,raw:`) instead. No PTY is allocated then, so line endings are
+/// not translated to `\r\n`, stderr is included in the output, and the exit
+/// code is known: see `shell_output` and `ShellStream::exit_code`.
 pub trait AdbShell {
   fn shell_exec(&mut self, cmd: &str) -> AdbResult<Vec<u8>>;
+  fn shell_exec_timeout(&mut self, cmd: &str, timeout: Duration) -> AdbResult<Vec<u8>>;
+  /// Runs `cmd` like `shell_exec`, also returning its exit code.
+  fn shell_output(&mut self, cmd: &str) -> AdbResult<ShellOutput>;
+  fn shell_stream(&mut self, cmd: &str) -> AdbResult<ShellStream>;
+}
+
+/// Output and exit code of a command run with `AdbShell::shell_output`.
+#[derive(Debug, Clone)]
+pub struct ShellOutput {
+  pub output: Vec<u8>,
+  /// Only known on devices supporting shell protocol v2.
+  pub exit_code: Option<u8>,
 }
 
 impl AdbShell for AdbConnection {
   fn shell_exec(&mut self, cmd: &str) -> AdbResult<Vec<u8>> {
-    let stream = self.open_stream(&format!("shell:{}", cmd))?;
-    let mut buf = vec![];
+    let mut stream = self.shell_stream(cmd)?;
+    stream.read_all()
+  }
+
+  fn shell_output(&mut self, cmd: &str) -> AdbResult<ShellOutput> {
+    let mut stream = self.shell_stream(cmd)?;
+    let output = stream.read_all()?;
+    Ok(ShellOutput {
+      output,
+      exit_code: stream.exit_code(),
+    })
+  }
+
+  fn shell_exec_timeout(&mut self, cmd: &str, timeout: Duration) -> AdbResult<Vec<u8>> {
+    let mut stream = self.shell_stream(cmd)?;
+    stream.set_timeout(Some(timeout));
+    stream.read_all()
+  }
+
+  fn shell_stream(&mut self, cmd: &str) -> AdbResult<ShellStream> {
+    if self.has_feature("shell_v2") {
+      let stream = self.open_stream(&format!("shell,v2,raw:{}", cmd))?;
+      Ok(ShellStream::with_protocol(stream, ShellProtocol::V2))
+    } else {
+      let stream = self.open_stream(&format!("shell:{}", cmd))?;
+      Ok(ShellStream::new(stream))
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ShellProtocol {
+  Raw,
+  V2,
+}
+
+// shell protocol v2 packet ids
+const SHELL_V2_STDIN: u8 = 0;
+const SHELL_V2_STDOUT: u8 = 1;
+const SHELL_V2_STDERR: u8 = 2;
+const SHELL_V2_EXIT: u8 = 3;
+const SHELL_V2_CLOSE_STDIN: u8 = 4;
+const SHELL_V2_HEADER_LEN: usize = 5;
+
+/// A running command on the device.
+///
+/// Output is yielded as it arrives, either chunk by chunk with `recv_chunk`
+/// or through the `Read` impl. Bytes written through the `Write` impl are
+/// forwarded to the command's stdin. Dropping the stream (or calling `close`)
+/// closes it on the device side.
+///
+/// If the device supports shell protocol v2, stdout and stderr are both
+/// yielded as output, the exit code is available from `exit_code`, and
+/// closing the stream makes adbd hang up the command.
+#[derive(Debug)]
+pub struct ShellStream {
+  stream: AdbStream,
+  protocol: ShellProtocol,
+  deadline: Option<Instant>,
+  pending: VecDeque<Bytes>,
+  v2_buf: BytesMut,
+  exit_code: Option<u8>,
+  awaiting_ok: bool,
+  eof: bool,
+  closed: bool,
+}
+
+impl ShellStream {
+  pub(crate) fn new(stream: AdbStream) -> Self {
+    Self::with_protocol(stream, ShellProtocol::Raw)
+  }
+
+  fn with_protocol(stream: AdbStream, protocol: ShellProtocol) -> Self {
+    ShellStream {
+      stream,
+      protocol,
+      deadline: None,
+      pending: VecDeque::new(),
+      v2_buf: BytesMut::new(),
+      exit_code: None,
+      awaiting_ok: false,
+      eof: false,
+      closed: false,
+    }
+  }
+
+  /// Limits how long the command may keep running, measured from now.
+  ///
+  /// Once the timeout expires, the stream is closed and any pending or
+  /// later operation fails with `AdbError::Timeout`.
+  pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+    self.deadline = timeout.map(|timeout| Instant::now() + timeout);
+  }
+
+  pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+    self.deadline = deadline;
+  }
+
+  /// Returns a handle that closes the stream from another thread, like
+  /// `close`. Pending and later reads and writes fail with
+  /// `AdbError::Cancelled`.
+  pub fn cancel_handle(&self) -> CancelHandle {
+    self.stream.cancel_handle()
+  }
+
+  /// Exit code of the command, available after the output has been fully
+  /// read from a shell protocol v2 stream.
+  pub fn exit_code(&self) -> Option<u8> {
+    self.exit_code
+  }
 
+  /// Returns the next chunk of output, or `None` once the command has exited.
+  pub fn recv_chunk(&mut self) -> AdbResult<Option<Bytes>> {
     loop {
-      let packet = stream.recv()?;
-      match packet.command {
-        Command::A_WRTE => {
-          buf.write_all(&packet.payload)?;
-          stream.send_ok()?;
+      if let Some(chunk) = self.pending.pop_front() {
+        return Ok(Some(chunk));
+      }
+
+      if self.eof {
+        return Ok(None);
+      }
+
+      self.pump()?;
+    }
+  }
+
+  pub(crate) fn read_all(&mut self) -> AdbResult<Vec<u8>> {
+    let mut buf = vec![];
+
+    while let Some(chunk) = self.recv_chunk()? {
+      buf.write_all(&chunk)?;
+    }
+
+    Ok(buf)
+  }
+
+  pub fn max_data_len(&self) -> usize {
+    match self.protocol {
+      ShellProtocol::Raw => self.stream.max_data_len(),
+      ShellProtocol::V2 => self.stream.max_data_len() - SHELL_V2_HEADER_LEN,
+    }
+  }
+
+  /// Writes `data` to the command's stdin.
+  pub fn send_stdin(&mut self, data: &[u8]) -> AdbResult<()> {
+    for chunk in data.chunks(self.max_data_len()) {
+      self.send_packet(SHELL_V2_STDIN, chunk)?;
+    }
+    Ok(())
+  }
+
+  /// Closes the command's stdin without closing the stream.
+  ///
+  /// Only supported by shell protocol v2, this is a no-op otherwise.
+  pub fn close_stdin(&mut self) -> AdbResult<()> {
+    if self.protocol == ShellProtocol::V2 {
+      self.send_packet(SHELL_V2_CLOSE_STDIN, &[])?;
+    }
+    Ok(())
+  }
+
+  /// Returns an iterator over the lines of output.
+  pub fn lines(self) -> Lines<BufReader<Self>> {
+    BufReader::new(self).lines()
+  }
+
+  /// Closes the stream, terminating the command's connection to the host.
+  pub fn close(mut self) -> AdbResult<()> {
+    self.send_close()
+  }
+
+  fn send_close(&mut self) -> AdbResult<()> {
+    if self.closed {
+      return Ok(());
+    }
+    self.closed = true;
+
+    if self.eof {
+      return Ok(());
+    }
+
+    self.stream.send_close()
+  }
+
+  fn send_packet(&mut self, id: u8, data: &[u8]) -> AdbResult<()> {
+    self.wait_ok()?;
+    let packet = match self.protocol {
+      ShellProtocol::Raw => AdbStreamPacket::new_write(data),
+      ShellProtocol::V2 => {
+        let mut bytes = BytesMut::with_capacity(SHELL_V2_HEADER_LEN + data.len());
+        bytes.put_u8(id);
+        bytes.put_u32_le(data.len() as u32);
+        bytes.put_slice(data);
+        AdbStreamPacket::new_write(bytes)
+      }
+    };
+    self.stream.send(packet)?;
+    self.awaiting_ok = true;
+    Ok(())
+  }
+
+  fn wait_ok(&mut self) -> AdbResult<()> {
+    while self.awaiting_ok {
+      if self.eof {
+        return Err(AdbError::Disconnected);
+      }
+      self.pump()?;
+    }
+    Ok(())
+  }
+
+  fn pump(&mut self) -> AdbResult<()> {
+    let packet = match self.stream.recv_deadline(self.deadline) {
+      Ok(packet) => packet,
+      Err(err) => {
+        if let AdbError::Timeout | AdbError::Cancelled = err {
+          self.send_close().ok();
         }
-        Command::A_CLSE => {
-          break;
+        return Err(err);
+      }
+    };
+
+    match packet.command {
+      Command::A_WRTE => {
+        self.stream.send_ok()?;
+        match self.protocol {
+          ShellProtocol::Raw => {
+            if !packet.payload.is_empty() {
+              self.pending.push_back(packet.payload);
+            }
+          }
+          ShellProtocol::V2 => {
+            self.v2_buf.extend_from_slice(&packet.payload);
+            self.decode_v2();
+          }
         }
-        cmd => return Err(AdbError::UnexpectedCommand(cmd)),
       }
+      Command::A_OKAY => {
+        self.awaiting_ok = false;
+      }
+      Command::A_CLSE => {
+        self.eof = true;
+      }
+      cmd => return Err(AdbError::UnexpectedCommand(cmd)),
     }
+    Ok(())
+  }
 
-    Ok(buf)
+  fn decode_v2(&mut self) {
+    use bytes::{ByteOrder, LittleEndian};
+
+    while self.v2_buf.len() >= SHELL_V2_HEADER_LEN {
+      let len = LittleEndian::read_u32(&self.v2_buf[1..]) as usize;
+      if self.v2_buf.len() < SHELL_V2_HEADER_LEN + len {
+        break;
+      }
+
+      let id = self.v2_buf[0];
+      self.v2_buf.advance(SHELL_V2_HEADER_LEN);
+      let data = self.v2_buf.split_to(len).freeze();
+
+      match id {
+        SHELL_V2_STDOUT | SHELL_V2_STDERR => {
+          if !data.is_empty() {
+            self.pending.push_back(data);
+          }
+        }
+        SHELL_V2_EXIT => {
+          self.exit_code = data.first().cloned();
+        }
+        id => {
+          warn!("shell v2 packet discarded: id = {}, len = {}", id, len);
+        }
+      }
+    }
+  }
+}
+
+impl Read for ShellStream {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if buf.is_empty() {
+      return Ok(0);
+    }
+
+    let mut chunk = match self.recv_chunk()? {
+      Some(chunk) => chunk,
+      None => return Ok(0),
+    };
+
+    let n = ::std::cmp::min(buf.len(), chunk.len());
+    buf[..n].copy_from_slice(&chunk.split_to(n));
+    if !chunk.is_empty() {
+      self.pending.push_front(chunk);
+    }
+    Ok(n)
+  }
+}
+
+impl Write for ShellStream {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let n = ::std::cmp::min(buf.len(), self.max_data_len());
+    self.send_stdin(&buf[..n])?;
+    Ok(n)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.wait_ok().map_err(Into::into)
+  }
+}
+
+impl Drop for ShellStream {
+  fn drop(&mut self) {
+    self.send_close().ok();
+  }
+}
+
+#[derive(Debug, Clone)]
+enum ShellWrapper {
+  RunAs(String),
+  Su,
+}
+
+/// Builds a command line for the device's `/system/bin/sh`.
+///
+/// Every argument is quoted, so paths containing spaces or quotes are passed
+/// to the program verbatim:
+///
+/// ```
+/// # use adb_rs::shell::ShellCommand;
+/// let cmd = ShellCommand::new("ls")
+///   .arg("-l")
+///   .arg("/sdcard/My Photos")
+///   .run_as("com.example.app");
+/// assert_eq!(cmd.service(), "shell:run-as com.example.app ls -l '/sdcard/My Photos'");
+/// ```
+#[derive(Debug, Clone)]
+pub struct ShellCommand {
+  program: String,
+  args: Vec<String>,
+  envs: Vec<(String, String)>,
+  current_dir: Option<String>,
+  wrapper: Option<ShellWrapper>,
+}
+
+impl ShellCommand {
+  pub fn new<S: Into<String>>(program: S) -> Self {
+    ShellCommand {
+      program: program.into(),
+      args: vec![],
+      envs: vec![],
+      current_dir: None,
+      wrapper: None,
+    }
+  }
+
+  pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+    self.args.push(arg.into());
+    self
+  }
+
+  pub fn args<I, S>(mut self, args: I) -> Self
+  where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+  {
+    self.args.extend(args.into_iter().map(Into::into));
+    self
+  }
+
+  /// Sets an environment variable for the program. The value is quoted.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `key` is not a valid shell variable name
+  /// (`[A-Za-z_][A-Za-z0-9_]*`), as it cannot be quoted.
+  pub fn env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+    let key = key.into();
+    let is_valid = key
+      .chars()
+      .enumerate()
+      .all(|(i, c)| c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+    assert!(
+      is_valid && !key.is_empty(),
+      "invalid environment variable name: {:?}",
+      key
+    );
+    self.envs.push((key, value.into()));
+    self
+  }
+
+  /// Changes to `dir` before running the program.
+  pub fn current_dir<S: Into<String>>(mut self, dir: S) -> Self {
+    self.current_dir = Some(dir.into());
+    self
+  }
+
+  /// Runs the command as the given (debuggable) package through `run-as`.
+  pub fn run_as<S: Into<String>>(mut self, package: S) -> Self {
+    self.wrapper = Some(ShellWrapper::RunAs(package.into()));
+    self
+  }
+
+  /// Runs the command as root through `su 0`.
+  pub fn su(mut self) -> Self {
+    self.wrapper = Some(ShellWrapper::Su);
+    self
+  }
+
+  /// Returns the quoted command line.
+  pub fn command_line(&self) -> String {
+    let mut line = String::new();
+
+    if let Some(ref dir) = self.current_dir {
+      line.push_str("cd ");
+      line.push_str(&quote(dir));
+      line.push_str(" && ");
+    }
+
+    for (key, value) in &self.envs {
+      line.push_str(key);
+      line.push('=');
+      line.push_str(&quote(value));
+      line.push(' ');
+    }
+
+    line.push_str(&quote(&self.program));
+    for arg in &self.args {
+      line.push(' ');
+      line.push_str(&quote(arg));
+    }
+
+    let prefix = match self.wrapper {
+      Some(ShellWrapper::RunAs(ref package)) => format!("run-as {}", quote(package)),
+      Some(ShellWrapper::Su) => "su 0".to_string(),
+      None => return line,
+    };
+
+    if self.current_dir.is_some() || !self.envs.is_empty() {
+      format!("{} sh -c {}", prefix, quote(&line))
+    } else {
+      format!("{} {}", prefix, line)
+    }
+  }
+
+  /// Returns the `shell:` service string to pass to `AdbConnection::open_stream`.
+  pub fn service(&self) -> String {
+    format!("shell:{}", self.command_line())
+  }
+}
+
+impl ::std::fmt::Display for ShellCommand {
+  fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+    f.write_str(&self.command_line())
+  }
+}
+
+/// Quotes `s` as a single word for `/system/bin/sh`.
+///
+/// Words containing `=` are quoted too, so that the shell does not take a
+/// first word such as `A=b` for an assignment.
+pub fn quote(s: &str) -> Cow<'_, str> {
+  let is_safe = |c: char| c.is_ascii_alphanumeric() || "@%+:,./_-".contains(c);
+  if !s.is_empty() && s.chars().all(is_safe) {
+    return Cow::Borrowed(s);
+  }
+
+  Cow::Owned(format!("'{}'", s.replace('\'', r"'\''")))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn quote_safe_words() {
+    assert_eq!(quote("ls"), "ls");
+    assert_eq!(quote("/sdcard/DCIM/a-b_c.jpg"), "/sdcard/DCIM/a-b_c.jpg");
+    assert_eq!(quote("user@host:1,2+3%"), "user@host:1,2+3%");
+  }
+
+  #[test]
+  fn quote_unsafe_words() {
+    assert_eq!(quote(""), "''");
+    assert_eq!(quote("My Photos"), "'My Photos'");
+    assert_eq!(quote("it's"), r"'it'\''s'");
+    assert_eq!(quote("'"), r"''\'''");
+    assert_eq!(quote("A=b"), "'A=b'");
+    assert_eq!(quote("--opt=1"), "'--opt=1'");
+    assert_eq!(quote("$HOME;rm -rf *"), "'$HOME;rm -rf *'");
+    assert_eq!(quote("a\nb"), "'a\nb'");
+    assert_eq!(quote("фото.jpg"), "'фото.jpg'");
+  }
+
+  #[test]
+  fn command_line() {
+    let cmd = ShellCommand::new("A=b").arg("").arg("x y");
+    assert_eq!(cmd.command_line(), "'A=b' '' 'x y'");
+
+    let cmd = ShellCommand::new("am")
+      .arg("start")
+      .env("FOO_1", "a b")
+      .current_dir("/data/local/tmp");
+    assert_eq!(
+      cmd.command_line(),
+      "cd /data/local/tmp && FOO_1='a b' am start"
+    );
+    assert_eq!(
+      cmd.su().command_line(),
+      r"su 0 sh -c 'cd /data/local/tmp && FOO_1='\''a b'\'' am start'"
+    );
+  }
+
+  #[test]
+  fn env_accepts_variable_names() {
+    ShellCommand::new("true")
+      .env("_", "")
+      .env("a1", "")
+      .env("PATH", "");
+  }
+
+  #[test]
+  #[should_panic(expected = "invalid environment variable name")]
+  fn env_rejects_injection() {
+    ShellCommand::new("true").env("X=1; rm -rf /data/x #", "");
+  }
+
+  #[test]
+  #[should_panic(expected = "invalid environment variable name")]
+  fn env_rejects_leading_digit() {
+    ShellCommand::new("true").env("1A", "");
+  }
+
+  #[test]
+  #[should_panic(expected = "invalid environment variable name")]
+  fn env_rejects_empty_name() {
+    ShellCommand::new("true").env("", "");
   }
 }
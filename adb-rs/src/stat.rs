@@ -0,0 +1,289 @@
+use bytes::{ByteOrder, LittleEndian};
+use std::time::{Duration, SystemTime};
+
+use super::client::*;
+use super::exec::exec_status;
+use super::shell::ShellCommand;
+use super::sync::*;
+use crate::result::*;
+
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+
+/// Metadata of a remote file, as reported by the sync `STAT` request (or
+/// `LST2` on devices advertising `stat_v2`).
+///
+/// Symlinks are not followed. Legacy `STAT` only carries the mode, a 32-bit
+/// size and mtime, and reports errors as all zeros; the remaining fields are
+/// zero in that case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteMetadata {
+  error: u32,
+  dev: u64,
+  ino: u64,
+  mode: u32,
+  nlink: u32,
+  uid: u32,
+  gid: u32,
+  size: u64,
+  atime: i64,
+  mtime: i64,
+  ctime: i64,
+}
+
+impl RemoteMetadata {
+  pub(crate) fn new(mode: u32, size: u64, mtime: i64) -> Self {
+    RemoteMetadata {
+      error: 0,
+      dev: 0,
+      ino: 0,
+      mode,
+      nlink: 0,
+      uid: 0,
+      gid: 0,
+      size,
+      atime: 0,
+      mtime,
+      ctime: 0,
+    }
+  }
+
+  pub(crate) fn decode_stat_v1(bytes: &[u8]) -> AdbResult<Self> {
+    let id = LittleEndian::read_u32(bytes);
+    if id != SyncCommand::STAT as u32 {
+      return Err(AdbError::UnexpectedData(bytes.to_vec()));
+    }
+
+    Ok(RemoteMetadata::new(
+      LittleEndian::read_u32(&bytes[4..]),
+      LittleEndian::read_u32(&bytes[8..]) as u64,
+      LittleEndian::read_u32(&bytes[12..]) as i64,
+    ))
+  }
+
+  // decodes the fields shared by `sync_stat_v2` and `sync_dent_v2`, after the id
+  pub(crate) fn decode_v2(bytes: &[u8]) -> Self {
+    RemoteMetadata {
+      error: LittleEndian::read_u32(&bytes[4..]),
+      dev: LittleEndian::read_u64(&bytes[8..]),
+      ino: LittleEndian::read_u64(&bytes[16..]),
+      mode: LittleEndian::read_u32(&bytes[24..]),
+      nlink: LittleEndian::read_u32(&bytes[28..]),
+      uid: LittleEndian::read_u32(&bytes[32..]),
+      gid: LittleEndian::read_u32(&bytes[36..]),
+      size: LittleEndian::read_u64(&bytes[40..]),
+      atime: LittleEndian::read_i64(&bytes[48..]),
+      mtime: LittleEndian::read_i64(&bytes[56..]),
+      ctime: LittleEndian::read_i64(&bytes[64..]),
+    }
+  }
+
+  /// The `errno` reported by the device, for `stat_v2` replies.
+  pub fn error(&self) -> Option<u32> {
+    if self.error != 0 {
+      Some(self.error)
+    } else {
+      None
+    }
+  }
+
+  /// Describes `error()`, e.g. "No such file or directory".
+  pub fn error_message(&self) -> Option<String> {
+    self.error().map(strerror)
+  }
+
+  pub fn exists(&self) -> bool {
+    self.error == 0 && self.mode != 0
+  }
+
+  pub fn is_dir(&self) -> bool {
+    self.mode & S_IFMT == S_IFDIR
+  }
+
+  pub fn is_file(&self) -> bool {
+    self.mode & S_IFMT == S_IFREG
+  }
+
+  pub fn is_symlink(&self) -> bool {
+    self.mode & S_IFMT == S_IFLNK
+  }
+
+  /// Full `st_mode`, including the file type bits.
+  pub fn mode(&self) -> u32 {
+    self.mode
+  }
+
+  /// Permission bits (`st_mode & 0o7777`).
+  pub fn permissions(&self) -> u32 {
+    self.mode & 0o7777
+  }
+
+  pub fn len(&self) -> u64 {
+    self.size
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.size == 0
+  }
+
+  /// Modification time in seconds since the Unix epoch.
+  pub fn mtime(&self) -> i64 {
+    self.mtime
+  }
+
+  pub fn modified(&self) -> SystemTime {
+    to_system_time(self.mtime)
+  }
+
+  /// Access time in seconds since the Unix epoch, `stat_v2` only.
+  pub fn atime(&self) -> i64 {
+    self.atime
+  }
+
+  /// Status change time in seconds since the Unix epoch, `stat_v2` only.
+  pub fn ctime(&self) -> i64 {
+    self.ctime
+  }
+
+  pub fn dev(&self) -> u64 {
+    self.dev
+  }
+
+  pub fn ino(&self) -> u64 {
+    self.ino
+  }
+
+  pub fn nlink(&self) -> u32 {
+    self.nlink
+  }
+
+  pub fn uid(&self) -> u32 {
+    self.uid
+  }
+
+  pub fn gid(&self) -> u32 {
+    self.gid
+  }
+}
+
+fn to_system_time(secs: i64) -> SystemTime {
+  if secs >= 0 {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64)
+  } else {
+    SystemTime::UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+  }
+}
+
+// Android's (Linux) errno values, which may differ from the host's.
+pub(crate) fn strerror(errno: u32) -> String {
+  match errno {
+    1 => "Operation not permitted".to_string(),
+    2 => "No such file or directory".to_string(),
+    5 => "I/O error".to_string(),
+    13 => "Permission denied".to_string(),
+    17 => "File exists".to_string(),
+    20 => "Not a directory".to_string(),
+    21 => "Is a directory".to_string(),
+    28 => "No space left on device".to_string(),
+    30 => "Read-only file system".to_string(),
+    36 => "File name too long".to_string(),
+    40 => "Too many symbolic links encountered".to_string(),
+    errno => format!("errno {}", errno),
+  }
+}
+
+pub trait AdbStat {
+  fn stat(&mut self, remote_path: &str) -> AdbResult<RemoteMetadata>;
+}
+
+impl AdbStat for AdbConnection {
+  fn stat(&mut self, remote_path: &str) -> AdbResult<RemoteMetadata> {
+    let stat_v2 = self.has_feature("stat_v2");
+    let stream = self.open_stream("sync:")?;
+    let metadata = stat(&stream, remote_path, stat_v2, false)?;
+    stream.sync_quit()?;
+    Ok(metadata)
+  }
+}
+
+/// Sends `STAT`, or `LST2` (`STA2` if `follow` is set) if `stat_v2` is set,
+/// and decodes the reply.
+///
+/// Legacy `STAT` never follows symlinks.
+pub(crate) fn stat(
+  stream: &AdbStream,
+  remote_path: &str,
+  stat_v2: bool,
+  follow: bool,
+) -> AdbResult<RemoteMetadata> {
+  let stat = if stat_v2 && follow {
+    debug!("STA2");
+    SyncCommand::new_stat_v2(remote_path)
+  } else if stat_v2 {
+    debug!("LST2");
+    SyncCommand::new_lstat_v2(remote_path)
+  } else {
+    debug!("STAT");
+    SyncCommand::new_stat(remote_path)
+  };
+  let mut reader = SyncReader::new(stream);
+  stream.send(AdbStreamPacket::new_write(stat))?;
+  reader.wait_ok()?;
+
+  let metadata = match reader.read_record()? {
+    SyncRecord::Stat(metadata) => metadata,
+    record => return Err(record.into_error()),
+  };
+  debug!("stat ok: {:?}", metadata);
+
+  Ok(metadata)
+}
+
+/// Stats `remote_path` with `stat -L` through `exec:`, following symlinks
+/// on devices without `stat_v2`.
+///
+/// The size is not truncated and `dev` and `ino` are known, unlike with
+/// legacy `STAT`, which this otherwise matches: only the mode, size and
+/// mtime are set, and a missing file is reported as all zeros.
+pub(crate) fn stat_follow_exec(
+  conn: &AdbConnection,
+  remote_path: &str,
+) -> AdbResult<RemoteMetadata> {
+  let cmd = ShellCommand::new("stat")
+    .arg("-L")
+    .arg("-c")
+    .arg("%f %s %Y %d %i")
+    .arg("--")
+    .arg(remote_path);
+  let (output, status) = exec_status(conn, &format!("{} 2>/dev/null", cmd.command_line()))?;
+  let metadata = match (status, output.lines().count()) {
+    (0, 1) => parse_stat_fields(&output),
+    (1, 0) => Some(RemoteMetadata::new(0, 0, 0)),
+    _ => None,
+  };
+  metadata.ok_or_else(|| {
+    AdbError::Fail(format!(
+      "failed to stat remote object '{}': {}",
+      remote_path,
+      output.trim()
+    ))
+  })
+}
+
+// parses `%f %s %Y %d %i`
+fn parse_stat_fields(fields: &str) -> Option<RemoteMetadata> {
+  let fields: Vec<&str> = fields.split_whitespace().collect();
+  if fields.len() != 5 {
+    return None;
+  }
+  let mut metadata = RemoteMetadata::new(
+    u32::from_str_radix(fields[0], 16).ok()?,
+    fields[1].parse().ok()?,
+    fields[2].parse().ok()?,
+  );
+  metadata.dev = fields[3].parse().ok()?;
+  metadata.ino = fields[4].parse().ok()?;
+  Some(metadata)
+}
@@ -1,11 +1,16 @@
 use bytes::*;
+use std::borrow::Borrow;
 use std::io::prelude::*;
 
 use crate::client::{AdbStream, AdbStreamPacket, Command};
 use crate::result::*;
+use crate::stat::RemoteMetadata;
+
+/// Largest payload of a `DATA` record adbd accepts (`SYNC_DATA_MAX`).
+pub const SYNC_DATA_MAX: usize = 64 * 1024;
 
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum SyncCommand {
   LIST = 0x5453494c,
   RECV = 0x56434552,
@@ -17,6 +22,12 @@ pub enum SyncCommand {
   DONE = 0x454e4f44,
   QUIT = 0x54495551,
   FAIL = 0x4c494146,
+  STA2 = 0x32415453,
+  LST2 = 0x3254534c,
+  LIS2 = 0x3253494c,
+  DNT2 = 0x32544e44,
+  SND2 = 0x32444e53,
+  RCV2 = 0x32564352,
 }
 
 impl SyncCommand {
@@ -30,7 +41,26 @@ impl SyncCommand {
     let mut bytes = BytesMut::with_capacity(8 + data.len());
     bytes.put_slice(&header.bytes());
     bytes.put_slice(data);
-    SyncPacket { header, bytes }
+    SyncPacket { bytes }
+  }
+
+  /// `SND2` followed by its `sync_send_v2` setup record.
+  pub fn new_send_v2(name: &str, mode: u32, flags: u32) -> SyncPacket {
+    let mut packet = Self::new_request(SyncCommand::SND2, name);
+    packet.bytes.reserve(12);
+    packet.bytes.put_u32_le(SyncCommand::SND2 as u32);
+    packet.bytes.put_u32_le(mode);
+    packet.bytes.put_u32_le(flags);
+    packet
+  }
+
+  /// `RCV2` followed by its `sync_recv_v2` setup record.
+  pub fn new_recv_v2(name: &str, flags: u32) -> SyncPacket {
+    let mut packet = Self::new_request(SyncCommand::RCV2, name);
+    packet.bytes.reserve(8);
+    packet.bytes.put_u32_le(SyncCommand::RCV2 as u32);
+    packet.bytes.put_u32_le(flags);
+    packet
   }
 
   pub fn new_stat(name: &str) -> SyncPacket {
@@ -43,17 +73,39 @@ impl SyncCommand {
     let mut bytes = BytesMut::with_capacity(8 + data.len());
     bytes.put_slice(&header.bytes());
     bytes.put_slice(data);
-    SyncPacket { header, bytes }
+    SyncPacket { bytes }
+  }
+
+  pub fn new_recv(name: &str) -> SyncPacket {
+    Self::new_request(SyncCommand::RECV, name)
+  }
+
+  pub fn new_list(name: &str) -> SyncPacket {
+    Self::new_request(SyncCommand::LIST, name)
+  }
+
+  pub fn new_stat_v2(name: &str) -> SyncPacket {
+    Self::new_request(SyncCommand::STA2, name)
   }
 
-  pub fn new_data(max_size: usize) -> SyncPacket {
+  pub fn new_lstat_v2(name: &str) -> SyncPacket {
+    Self::new_request(SyncCommand::LST2, name)
+  }
+
+  pub fn new_list_v2(name: &str) -> SyncPacket {
+    Self::new_request(SyncCommand::LIS2, name)
+  }
+
+  fn new_request(cmd: SyncCommand, name: &str) -> SyncPacket {
+    let data = name.as_bytes();
     let header = SyncHeader {
-      id: SyncCommand::DATA as u32,
-      length: max_size as u32 - 8,
+      id: cmd as u32,
+      length: data.len() as u32,
     };
-    let mut bytes = BytesMut::with_capacity(max_size);
+    let mut bytes = BytesMut::with_capacity(8 + data.len());
     bytes.put_slice(&header.bytes());
-    SyncPacket { header, bytes }
+    bytes.put_slice(data);
+    SyncPacket { bytes }
   }
 
   pub fn new_done(mtime: u32) -> [u8; 8] {
@@ -81,19 +133,10 @@ pub struct SyncHeader {
 
 #[derive(Debug, Default)]
 pub struct SyncPacket {
-  pub header: SyncHeader,
   pub bytes: BytesMut,
 }
 
 impl SyncPacket {
-  pub fn extend<T: AsRef<[u8]>>(&mut self, data: T) {
-    self.bytes.extend_from_slice(data.as_ref());
-  }
-
-  pub fn len(&self) -> usize {
-    self.bytes.len()
-  }
-
   // pub fn payload_len(&self) -> usize {
   //   self.bytes.len() - 8
   // }
@@ -101,15 +144,6 @@ impl SyncPacket {
   // pub fn payload_slice(&self) -> &[u8] {
   //   &self.bytes[8..]
   // }
-
-  pub fn read_payload_from<R: Read>(&mut self, r: &mut R) -> AdbResult<usize> {
-    self.bytes.resize(self.bytes.capacity(), 0);
-    let n = r.read(&mut self.bytes[8..])?;
-    self.bytes.truncate(8 + n);
-    self.header.length = n as u32;
-    (&mut self.bytes[0..8]).write_all(&self.header.bytes())?;
-    Ok(n)
-  }
 }
 
 impl AsRef<[u8]> for SyncPacket {
@@ -119,15 +153,6 @@ impl AsRef<[u8]> for SyncPacket {
 }
 
 impl SyncHeader {
-  pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
-    if bytes.len() != 8 {
-      return None;
-    }
-    let id = LittleEndian::read_u32(&bytes);
-    let length = LittleEndian::read_u32(&bytes[4..]);
-    Some(SyncHeader { id, length })
-  }
-
   fn bytes(&self) -> [u8; 8] {
     let mut header_bytes = [0; 8];
     LittleEndian::write_u32(&mut header_bytes, self.id);
@@ -136,50 +161,591 @@ impl SyncHeader {
   }
 }
 
+/// Appends `DATA` records read from `r` to `buf`, as many as fit in
+/// `max_len` bytes, so that they can be sent in one adb packet.
+///
+/// Returns the number of payload bytes read and whether `r` is at EOF.
+pub fn read_data_records<R: Read>(
+  r: &mut R,
+  buf: &mut BytesMut,
+  max_len: usize,
+) -> AdbResult<(u64, bool)> {
+  let mut total = 0;
+
+  while buf.len() + 8 < max_len {
+    let len = ::std::cmp::min(SYNC_DATA_MAX, max_len - buf.len() - 8);
+    let start = buf.len();
+    buf.resize(start + 8 + len, 0);
+
+    let mut n = 0;
+    while n < len {
+      match r.read(&mut buf[start + 8 + n..]) {
+        Ok(0) => break,
+        Ok(read) => n += read,
+        Err(ref err) if err.kind() == ::std::io::ErrorKind::Interrupted => {}
+        Err(err) => return Err(err.into()),
+      }
+    }
+
+    if n == 0 {
+      buf.truncate(start);
+      return Ok((total, true));
+    }
+
+    let header = SyncHeader {
+      id: SyncCommand::DATA as u32,
+      length: n as u32,
+    };
+    buf[start..start + 8].copy_from_slice(&header.bytes());
+    buf.truncate(start + 8 + n);
+    total += n as u64;
+
+    if n < len {
+      return Ok((total, true));
+    }
+  }
+
+  Ok((total, false))
+}
+
 pub trait SyncStreamExt {
-  fn sync_recv(&self) -> AdbResult<AdbStreamPacket>;
-  fn sync_recv_command(&self, cmd: Command) -> AdbResult<AdbStreamPacket> {
-    let packet = self.sync_recv()?;
+  fn sync_quit(&self) -> AdbResult<()>;
+}
+
+impl SyncStreamExt for AdbStream {
+  fn sync_quit(&self) -> AdbResult<()> {
+    debug!("QUIT");
+    let quit = SyncCommand::new_quit();
+    let packet = AdbStreamPacket::new_write(quit);
+    self.send(packet)?;
 
-    packet.check_command(cmd)?;
+    self.recv_command(Command::A_OKAY)?;
 
-    Ok(packet)
+    self.send_close()?;
+
+    self.recv_command(Command::A_CLSE)?;
+
+    Ok(())
   }
+}
 
-  fn sync_recv_ok(&self) -> AdbResult<()> {
-    self.sync_recv_command(Command::A_OKAY).map(|_| ())
+/// A record sent by the device in reply to a sync request.
+#[derive(Debug)]
+pub enum SyncRecord {
+  Okay,
+  Fail(String),
+  Data(Bytes),
+  Done,
+  Dent {
+    name: String,
+    metadata: RemoteMetadata,
+  },
+  Stat(RemoteMetadata),
+}
+
+impl SyncRecord {
+  /// Turns a record the caller did not expect into an error.
+  pub fn into_error(self) -> AdbError {
+    let id = match self {
+      SyncRecord::Fail(msg) => return AdbError::Fail(msg),
+      SyncRecord::Okay => SyncCommand::OKAY,
+      SyncRecord::Data(_) => SyncCommand::DATA,
+      SyncRecord::Done => SyncCommand::DONE,
+      SyncRecord::Dent { .. } => SyncCommand::DENT,
+      SyncRecord::Stat(_) => SyncCommand::STAT,
+    };
+    AdbError::UnexpectedData((id as u32).to_le_bytes().to_vec())
   }
 }
-impl SyncStreamExt for AdbStream {
-  fn sync_recv(&self) -> AdbResult<AdbStreamPacket> {
-    let packet = self.recv()?;
-
-    if packet.command == Command::A_WRTE && packet.payload.len() == 8 {
-      let sync_header = SyncHeader::from_bytes(&packet.payload);
-      if let Some(header) = sync_header {
-        if header.id == SyncCommand::FAIL as u32 {
-          return fail(self, header.length as usize);
-        }
+
+/// Reads the replies to sync requests from a stream.
+///
+/// adb packet boundaries are unrelated to sync record boundaries: a packet
+/// may carry several records, and a record may span several packets. The
+/// reader buffers the byte stream and decodes whole records from it.
+pub struct SyncReader<S> {
+  stream: S,
+  decoder: SyncDecoder,
+}
+
+impl<S: Borrow<AdbStream>> SyncReader<S> {
+  /// Reads from a borrowed or owned stream.
+  pub fn new(stream: S) -> Self {
+    SyncReader {
+      stream,
+      decoder: SyncDecoder::default(),
+    }
+  }
+
+  pub fn stream(&self) -> &AdbStream {
+    self.stream.borrow()
+  }
+
+  /// Waits for the device to acknowledge the last packet written.
+  ///
+  /// Records received in the meantime are buffered, except for a `FAIL`,
+  /// which is returned as an error: the device closes the stream after it,
+  /// so the acknowledgement may never come.
+  pub fn wait_ok(&mut self) -> AdbResult<()> {
+    loop {
+      if let Command::A_OKAY = self.recv()? {
+        return Ok(());
+      }
+      if let Some(err) = self.decoder.buffered_fail() {
+        return Err(err);
       }
     }
+  }
 
-    Ok(packet)
+  /// Reads the next record of a reply to anything but `LIST`/`LIS2`.
+  pub fn read_record(&mut self) -> AdbResult<SyncRecord> {
+    self.read_record_with_done_len(8)
   }
-}
 
-fn fail(stream: &AdbStream, len: usize) -> AdbResult<AdbStreamPacket> {
-  loop {
-    let packet = stream.recv().map_err(|_| AdbError::Disconnected)?;
-    match packet.command {
-      Command::A_OKAY => {
-        stream.send_ok()?;
+  /// Reads the next record of a reply to `LIST`, or `LIS2` if `ls_v2` is
+  /// set, whose `DONE` record is padded to the size of an entry.
+  pub fn read_list_record(&mut self, ls_v2: bool) -> AdbResult<SyncRecord> {
+    self.read_record_with_done_len(if ls_v2 { DENT_V2_LEN } else { DENT_V1_LEN })
+  }
+
+  fn read_record_with_done_len(&mut self, done_len: usize) -> AdbResult<SyncRecord> {
+    loop {
+      if let Some(record) = self.decoder.decode(done_len)? {
+        return Ok(record);
+      }
+      match self.recv()? {
+        Command::A_WRTE => {}
+        cmd => return Err(AdbError::UnexpectedCommand(cmd)),
       }
+    }
+  }
+
+  // receives a packet, buffering the payload of `A_WRTE`
+  fn recv(&mut self) -> AdbResult<Command> {
+    let packet = self.stream().recv()?;
+    match packet.command {
       Command::A_WRTE => {
-        return Err(AdbError::Fail(
-          String::from_utf8_lossy(&packet.payload[0..len]).to_string(),
-        ));
+        self.stream().send_ok()?;
+        self.decoder.extend(&packet.payload);
+      }
+      Command::A_OKAY => {}
+      Command::A_CLSE => {
+        return Err(
+          self
+            .decoder
+            .buffered_fail()
+            .unwrap_or(AdbError::Disconnected),
+        )
       }
       cmd => return Err(AdbError::UnexpectedCommand(cmd)),
     }
+    Ok(packet.command)
+  }
+}
+
+/// Decodes sync records from the bytes of a reply, however they were split
+/// into packets.
+#[derive(Debug, Default)]
+pub struct SyncDecoder {
+  buf: BytesMut,
+}
+
+impl SyncDecoder {
+  /// Buffers the payload of a packet.
+  pub fn extend(&mut self, data: &[u8]) {
+    self.buf.extend_from_slice(data);
+  }
+
+  /// Decodes the next record if it is fully buffered, `DONE` records being
+  /// `done_len` bytes long.
+  pub fn decode(&mut self, done_len: usize) -> AdbResult<Option<SyncRecord>> {
+    let record_len = match self.record_len(done_len)? {
+      Some(record_len) if record_len <= self.buf.len() => record_len,
+      _ => return Ok(None),
+    };
+    let id = LittleEndian::read_u32(&self.buf);
+    let bytes = self.buf.split_to(record_len).freeze();
+    decode_record(id, bytes).map(Some)
+  }
+
+  /// A complete `FAIL` record at the start of the buffer.
+  pub fn buffered_fail(&self) -> Option<AdbError> {
+    if self.buf.len() < 8 || LittleEndian::read_u32(&self.buf) != SyncCommand::FAIL as u32 {
+      return None;
+    }
+    let len = LittleEndian::read_u32(&self.buf[4..]) as usize;
+    self
+      .buf
+      .get(8..8 + len)
+      .map(|msg| AdbError::Fail(String::from_utf8_lossy(msg).to_string()))
+  }
+
+  // the length of the record at the start of the buffer, once enough of it
+  // is buffered to tell. Lengths sent by the device are capped, a malformed
+  // one would otherwise have the record buffered without limit.
+  fn record_len(&self, done_len: usize) -> AdbResult<Option<usize>> {
+    if self.buf.len() < 8 {
+      return Ok(None);
+    }
+    let id = LittleEndian::read_u32(&self.buf);
+    let len = LittleEndian::read_u32(&self.buf[4..]) as usize;
+
+    let (fixed_len, len) = if id == SyncCommand::OKAY as u32 {
+      (8, 0)
+    } else if id == SyncCommand::FAIL as u32 || id == SyncCommand::DATA as u32 {
+      (8, len)
+    } else if id == SyncCommand::DONE as u32 {
+      (done_len, 0)
+    } else if id == SyncCommand::STAT as u32 {
+      (STAT_V1_LEN, 0)
+    } else if id == SyncCommand::LST2 as u32 || id == SyncCommand::STA2 as u32 {
+      (STAT_V2_LEN, 0)
+    } else if id == SyncCommand::DENT as u32 || id == SyncCommand::DNT2 as u32 {
+      let fixed_len = if id == SyncCommand::DENT as u32 {
+        DENT_V1_LEN
+      } else {
+        DENT_V2_LEN
+      };
+      if self.buf.len() < fixed_len {
+        return Ok(None);
+      }
+      let name_len = LittleEndian::read_u32(&self.buf[fixed_len - 4..]) as usize;
+      (fixed_len, name_len)
+    } else {
+      return Err(AdbError::UnexpectedData(self.buf[..8].to_vec()));
+    };
+
+    if len > SYNC_DATA_MAX {
+      return Err(AdbError::UnexpectedData(self.buf[..8].to_vec()));
+    }
+    Ok(Some(fixed_len + len))
+  }
+}
+
+// sizes of the fixed part of the records
+const STAT_V1_LEN: usize = 16;
+const STAT_V2_LEN: usize = 72;
+const DENT_V1_LEN: usize = 20;
+const DENT_V2_LEN: usize = 76;
+
+fn decode_record(id: u32, bytes: Bytes) -> AdbResult<SyncRecord> {
+  let record = if id == SyncCommand::OKAY as u32 {
+    SyncRecord::Okay
+  } else if id == SyncCommand::FAIL as u32 {
+    SyncRecord::Fail(String::from_utf8_lossy(&bytes[8..]).to_string())
+  } else if id == SyncCommand::DATA as u32 {
+    SyncRecord::Data(bytes.slice_from(8))
+  } else if id == SyncCommand::DONE as u32 {
+    SyncRecord::Done
+  } else if id == SyncCommand::STAT as u32 {
+    SyncRecord::Stat(RemoteMetadata::decode_stat_v1(&bytes)?)
+  } else if id == SyncCommand::LST2 as u32 || id == SyncCommand::STA2 as u32 {
+    SyncRecord::Stat(RemoteMetadata::decode_v2(&bytes))
+  } else if id == SyncCommand::DENT as u32 {
+    // id, mode, size, mtime, namelen
+    let metadata = RemoteMetadata::new(
+      LittleEndian::read_u32(&bytes[4..]),
+      LittleEndian::read_u32(&bytes[8..]) as u64,
+      LittleEndian::read_u32(&bytes[12..]) as i64,
+    );
+    let name = String::from_utf8_lossy(&bytes[DENT_V1_LEN..]).to_string();
+    SyncRecord::Dent { name, metadata }
+  } else {
+    // id, error, dev, ino, mode, nlink, uid, gid, size, atime, mtime, ctime, namelen
+    let metadata = RemoteMetadata::decode_v2(&bytes);
+    let name = String::from_utf8_lossy(&bytes[DENT_V2_LEN..]).to_string();
+    SyncRecord::Dent { name, metadata }
+  };
+  Ok(record)
+}
+
+/// Reads the contents of a file sent as `DATA` records, up to `DONE`.
+pub struct SyncDataReader<S> {
+  reader: SyncReader<S>,
+  data: Bytes,
+  done: bool,
+  error: Option<AdbError>,
+}
+
+impl<S: Borrow<AdbStream>> SyncDataReader<S> {
+  pub fn new(reader: SyncReader<S>) -> Self {
+    SyncDataReader {
+      reader,
+      data: Bytes::new(),
+      done: false,
+      error: None,
+    }
+  }
+
+  /// The error behind the last failed `read`, which `Read` can only report
+  /// as an `io::Error`.
+  pub fn take_error(&mut self) -> Option<AdbError> {
+    self.error.take()
+  }
+
+  pub fn stream(&self) -> &AdbStream {
+    self.reader.stream()
+  }
+
+  /// Whether the `DONE` record has been read.
+  pub fn is_done(&self) -> bool {
+    self.done
+  }
+
+  /// Skips to the end of the records, in case the reader was not drained.
+  pub fn finish(&mut self) -> AdbResult<()> {
+    while !self.done {
+      self.next_record()?;
+    }
+    Ok(())
+  }
+
+  fn next_record(&mut self) -> AdbResult<()> {
+    match self.reader.read_record()? {
+      SyncRecord::Data(data) => self.data = data,
+      SyncRecord::Done => self.done = true,
+      record => return Err(record.into_error()),
+    }
+    Ok(())
+  }
+}
+
+impl<S: Borrow<AdbStream>> Read for SyncDataReader<S> {
+  fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+    while self.data.is_empty() && !self.done {
+      if let Err(err) = self.next_record() {
+        let io_err = ::std::io::Error::other(err.to_string());
+        self.error = Some(err);
+        return Err(io_err);
+      }
+    }
+
+    let n = ::std::cmp::min(buf.len(), self.data.len());
+    buf[..n].copy_from_slice(&self.data.split_to(n));
+    Ok(n)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn record(id: SyncCommand, len: u32, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&(id as u32).to_le_bytes());
+    bytes.extend_from_slice(&len.to_le_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+  }
+
+  fn data(payload: &[u8]) -> Vec<u8> {
+    record(SyncCommand::DATA, payload.len() as u32, payload)
+  }
+
+  fn fail(msg: &str) -> Vec<u8> {
+    record(SyncCommand::FAIL, msg.len() as u32, msg.as_bytes())
+  }
+
+  // id, mode, size, mtime, namelen; the mode goes where other records
+  // have their length
+  fn dent(name: &str) -> Vec<u8> {
+    let mut bytes = record(SyncCommand::DENT, 0o100644, &[]);
+    for field in &[3u32, 1_600_000_000, name.len() as u32] {
+      bytes.extend_from_slice(&field.to_le_bytes());
+    }
+    bytes.extend_from_slice(name.as_bytes());
+    bytes
+  }
+
+  fn dent_v2(name: &str) -> Vec<u8> {
+    let mut bytes = vec![0; DENT_V2_LEN];
+    LittleEndian::write_u32(&mut bytes, SyncCommand::DNT2 as u32);
+    LittleEndian::write_u32(&mut bytes[24..], 0o040755);
+    LittleEndian::write_u64(&mut bytes[40..], 1 << 40);
+    LittleEndian::write_u32(&mut bytes[DENT_V2_LEN - 4..], name.len() as u32);
+    bytes.extend_from_slice(name.as_bytes());
+    bytes
+  }
+
+  fn padded_done(len: usize) -> Vec<u8> {
+    let mut bytes = record(SyncCommand::DONE, 0, &[]);
+    bytes.resize(len, 0);
+    bytes
+  }
+
+  fn decode_all(decoder: &mut SyncDecoder, done_len: usize) -> Vec<SyncRecord> {
+    let mut records = vec![];
+    while let Some(record) = decoder.decode(done_len).unwrap() {
+      records.push(record);
+    }
+    records
+  }
+
+  #[test]
+  fn fail_sharing_a_packet_with_its_message() {
+    let mut decoder = SyncDecoder::default();
+    decoder.extend(&fail("No such file or directory"));
+
+    match decoder.buffered_fail() {
+      Some(AdbError::Fail(msg)) => assert_eq!(msg, "No such file or directory"),
+      err => panic!("unexpected {:?}", err),
+    }
+    match decoder.decode(8).unwrap() {
+      Some(SyncRecord::Fail(msg)) => assert_eq!(msg, "No such file or directory"),
+      record => panic!("unexpected {:?}", record),
+    }
+    assert!(decoder.decode(8).unwrap().is_none());
+  }
+
+  #[test]
+  fn message_split_across_packets() {
+    let bytes = fail("Permission denied");
+    let mut decoder = SyncDecoder::default();
+
+    for chunk in bytes[..bytes.len() - 1].chunks(5) {
+      decoder.extend(chunk);
+      assert!(decoder.buffered_fail().is_none());
+      assert!(decoder.decode(8).unwrap().is_none());
+    }
+
+    decoder.extend(&bytes[bytes.len() - 1..]);
+    assert!(decoder.buffered_fail().is_some());
+    match decoder.decode(8).unwrap() {
+      Some(SyncRecord::Fail(msg)) => assert_eq!(msg, "Permission denied"),
+      record => panic!("unexpected {:?}", record),
+    }
+  }
+
+  #[test]
+  fn multiple_records_in_one_packet() {
+    let mut packet = data(b"hello ");
+    packet.extend(data(b"world"));
+    packet.extend(record(SyncCommand::DONE, 0, &[]));
+    packet.extend(record(SyncCommand::OKAY, 0, &[]));
+
+    let mut decoder = SyncDecoder::default();
+    decoder.extend(&packet);
+    let records = decode_all(&mut decoder, 8);
+
+    assert_eq!(records.len(), 4);
+    match (&records[0], &records[1]) {
+      (SyncRecord::Data(a), SyncRecord::Data(b)) => {
+        assert_eq!(&a[..], b"hello ");
+        assert_eq!(&b[..], b"world");
+      }
+      records => panic!("unexpected {:?}", records),
+    }
+    assert!(matches!(records[2], SyncRecord::Done));
+    assert!(matches!(records[3], SyncRecord::Okay));
+  }
+
+  #[test]
+  fn data_split_mid_header() {
+    let bytes = data(&[7; 1000]);
+    let mut decoder = SyncDecoder::default();
+    decoder.extend(&bytes[..3]);
+    assert!(decoder.decode(8).unwrap().is_none());
+    decoder.extend(&bytes[3..500]);
+    assert!(decoder.decode(8).unwrap().is_none());
+    decoder.extend(&bytes[500..]);
+    match decoder.decode(8).unwrap() {
+      Some(SyncRecord::Data(payload)) => assert_eq!(&payload[..], &[7; 1000][..]),
+      record => panic!("unexpected {:?}", record),
+    }
+  }
+
+  #[test]
+  fn list_done_is_padded() {
+    let mut packet = dent("a.txt");
+    packet.extend(dent("b"));
+    packet.extend(padded_done(DENT_V1_LEN));
+    packet.extend(record(SyncCommand::OKAY, 0, &[]));
+
+    let mut decoder = SyncDecoder::default();
+    // the padding arrives separately
+    decoder.extend(&packet[..packet.len() - 20]);
+    let mut records = decode_all(&mut decoder, DENT_V1_LEN);
+    decoder.extend(&packet[packet.len() - 20..]);
+    records.extend(decode_all(&mut decoder, DENT_V1_LEN));
+
+    let names: Vec<_> = records
+      .iter()
+      .filter_map(|record| match record {
+        SyncRecord::Dent { name, metadata } => {
+          assert!(metadata.is_file());
+          assert_eq!(metadata.len(), 3);
+          Some(name.as_str())
+        }
+        _ => None,
+      })
+      .collect();
+    assert_eq!(names, ["a.txt", "b"]);
+    assert_eq!(records.len(), 4);
+    assert!(matches!(records[2], SyncRecord::Done));
+    assert!(matches!(records[3], SyncRecord::Okay));
+  }
+
+  #[test]
+  fn lis2_done_is_padded() {
+    let mut packet = dent_v2("dir");
+    packet.extend(padded_done(DENT_V2_LEN));
+
+    let mut decoder = SyncDecoder::default();
+    decoder.extend(&packet[..packet.len() - 1]);
+    let records = decode_all(&mut decoder, DENT_V2_LEN);
+    assert_eq!(records.len(), 1);
+    match &records[0] {
+      SyncRecord::Dent { name, metadata } => {
+        assert_eq!(name, "dir");
+        assert!(metadata.is_dir());
+        assert_eq!(metadata.len(), 1 << 40);
+      }
+      record => panic!("unexpected {:?}", record),
+    }
+
+    decoder.extend(&packet[packet.len() - 1..]);
+    assert!(matches!(
+      decoder.decode(DENT_V2_LEN).unwrap(),
+      Some(SyncRecord::Done)
+    ));
+    assert!(decoder.decode(DENT_V2_LEN).unwrap().is_none());
+  }
+
+  #[test]
+  fn stat_v1() {
+    let mut bytes = record(SyncCommand::STAT, 0o120777, &[]);
+    bytes.extend_from_slice(&12u32.to_le_bytes());
+    bytes.extend_from_slice(&1_600_000_000u32.to_le_bytes());
+
+    let mut decoder = SyncDecoder::default();
+    decoder.extend(&bytes);
+    match decoder.decode(8).unwrap() {
+      Some(SyncRecord::Stat(metadata)) => {
+        assert!(metadata.is_symlink());
+        assert_eq!(metadata.len(), 12);
+        assert_eq!(metadata.mtime(), 1_600_000_000);
+      }
+      record => panic!("unexpected {:?}", record),
+    }
+  }
+
+  #[test]
+  fn oversized_lengths_are_rejected() {
+    for id in &[SyncCommand::DATA, SyncCommand::FAIL] {
+      let mut decoder = SyncDecoder::default();
+      decoder.extend(&record(*id, SYNC_DATA_MAX as u32 + 1, &[]));
+      assert!(decoder.decode(8).is_err());
+    }
+
+    let mut decoder = SyncDecoder::default();
+    decoder.extend(&record(SyncCommand::DATA, SYNC_DATA_MAX as u32, &[]));
+    assert!(decoder.decode(8).unwrap().is_none());
+  }
+
+  #[test]
+  fn unknown_records_are_rejected() {
+    let mut decoder = SyncDecoder::default();
+    decoder.extend(b"WHAT\0\0\0\0");
+    assert!(decoder.decode(8).is_err());
   }
 }
@@ -0,0 +1,852 @@
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::client::*;
+use super::compression::Compression;
+use super::exec::AdbExec;
+use super::pull::readlink;
+use super::push::{local_file_id, local_link_target, push_file, PushOptions};
+use super::session::SyncSession;
+use super::shell::ShellCommand;
+use super::stat::RemoteMetadata;
+use crate::result::*;
+
+/// How `sync_dir` decides which files to transfer.
+#[derive(Debug, Clone, Default)]
+pub struct SyncDirOptions {
+  /// Deletes remote files and directories missing from the local tree.
+  pub delete: bool,
+  /// Compares files of the same size by SHA-256, computed on the device with
+  /// `sha256sum`, instead of by mtime.
+  pub checksum: bool,
+  /// Syncs the files and directories symlinks point to, instead of the
+  /// symlinks themselves.
+  pub follow_symlinks: bool,
+  /// Compares every pushed file with a hash computed on the device, as
+  /// `PushOptions::verify` does.
+  pub verify: bool,
+  pub compression: Compression,
+}
+
+impl SyncDirOptions {
+  fn push_options(&self) -> PushOptions {
+    PushOptions {
+      follow_symlinks: self.follow_symlinks,
+      verify: self.verify,
+      compression: self.compression,
+      ..Default::default()
+    }
+  }
+}
+
+/// A change `sync_dir` makes to the remote tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncAction {
+  /// Pushes a file missing on the device.
+  Create {
+    local_path: PathBuf,
+    remote_path: String,
+  },
+  /// Pushes a file that differs from the remote one.
+  Update {
+    local_path: PathBuf,
+    remote_path: String,
+  },
+  /// Deletes a remote file or directory tree.
+  Delete { remote_path: String },
+}
+
+impl SyncAction {
+  pub fn remote_path(&self) -> &str {
+    match *self {
+      SyncAction::Create {
+        ref remote_path, ..
+      }
+      | SyncAction::Update {
+        ref remote_path, ..
+      }
+      | SyncAction::Delete { ref remote_path } => remote_path,
+    }
+  }
+}
+
+impl fmt::Display for SyncAction {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      SyncAction::Create {
+        ref local_path,
+        ref remote_path,
+      } => write!(f, "create {} -> {}", local_path.display(), remote_path),
+      SyncAction::Update {
+        ref local_path,
+        ref remote_path,
+      } => write!(f, "update {} -> {}", local_path.display(), remote_path),
+      SyncAction::Delete { ref remote_path } => write!(f, "delete {}", remote_path),
+    }
+  }
+}
+
+/// The actions needed to bring a remote tree up to date.
+///
+/// Deletions come first, so that a remote file can be replaced by a
+/// directory of the same name and vice versa.
+#[derive(Debug, Default)]
+pub struct SyncPlan {
+  pub actions: Vec<SyncAction>,
+  /// Number of local files already up to date on the device.
+  pub unchanged: usize,
+}
+
+/// Outcome of a single action during `sync_dir`.
+#[derive(Debug)]
+pub struct SyncActionResult {
+  pub action: SyncAction,
+  /// Number of bytes sent, zero for deletions.
+  pub result: AdbResult<u64>,
+}
+
+/// Per-action results and totals of a `sync_dir`.
+#[derive(Debug, Default)]
+pub struct SyncDirSummary {
+  pub actions: Vec<SyncActionResult>,
+  /// Number of local files already up to date on the device.
+  pub unchanged: usize,
+}
+
+impl SyncDirSummary {
+  pub fn total_bytes(&self) -> u64 {
+    self
+      .actions
+      .iter()
+      .filter_map(|a| a.result.as_ref().ok())
+      .sum()
+  }
+
+  pub fn failed(&self) -> impl Iterator<Item = &SyncActionResult> {
+    self.actions.iter().filter(|a| a.result.is_err())
+  }
+
+  pub fn is_success(&self) -> bool {
+    self.failed().next().is_none()
+  }
+}
+
+pub trait AdbSyncDir {
+  /// Compares `local_dir` against `remote_dir` without changing anything,
+  /// returning the actions `sync_dir` would take.
+  fn plan_sync_dir<P: AsRef<Path>>(
+    &mut self,
+    local_dir: P,
+    remote_dir: &str,
+    options: &SyncDirOptions,
+  ) -> AdbResult<SyncPlan>;
+  /// Pushes the files under `local_dir` that are missing or differ in
+  /// `remote_dir`, like `adb sync`.
+  ///
+  /// Files are compared by size and mtime, or by checksum if requested.
+  /// Symlinks are compared by target, and a changed remote symlink is
+  /// deleted before being recreated.
+  /// Pushed files keep their permission bits and mtime, so an unchanged file
+  /// is skipped on the next sync. A failing action does not abort the sync;
+  /// its error is reported in the returned summary.
+  fn sync_dir<P: AsRef<Path>>(
+    &mut self,
+    local_dir: P,
+    remote_dir: &str,
+    options: &SyncDirOptions,
+  ) -> AdbResult<SyncDirSummary>;
+}
+
+impl AdbSyncDir for AdbConnection {
+  fn plan_sync_dir<P: AsRef<Path>>(
+    &mut self,
+    local_dir: P,
+    remote_dir: &str,
+    options: &SyncDirOptions,
+  ) -> AdbResult<SyncPlan> {
+    let session = SyncSession::open(self)?;
+    let mut planner = Planner {
+      conn: self,
+      session: &session,
+      follow_symlinks: options.follow_symlinks,
+      ancestors: vec![],
+      plan: Plan::new(options),
+    };
+    planner.walk(local_dir.as_ref(), remote_dir.trim_end_matches('/'), true)?;
+    let Plan {
+      deletes,
+      mut pushes,
+      mut unchanged,
+      candidates,
+      ..
+    } = planner.plan;
+    session.quit()?;
+
+    if !candidates.is_empty() {
+      let remote_paths = candidates.iter().map(|c| c.1.clone()).collect::<Vec<_>>();
+      let remote_hashes = remote_sha256(self, &remote_paths)?;
+      for (local_path, remote_path) in candidates {
+        if remote_hashes.get(&remote_path) == Some(&local_sha256(&local_path)?) {
+          unchanged += 1;
+        } else {
+          pushes.push(SyncAction::Update {
+            local_path,
+            remote_path,
+          });
+        }
+      }
+    }
+
+    let mut actions = deletes;
+    actions.append(&mut pushes);
+    Ok(SyncPlan { actions, unchanged })
+  }
+
+  fn sync_dir<P: AsRef<Path>>(
+    &mut self,
+    local_dir: P,
+    remote_dir: &str,
+    options: &SyncDirOptions,
+  ) -> AdbResult<SyncDirSummary> {
+    let plan = self.plan_sync_dir(local_dir, remote_dir, options)?;
+
+    let mut summary = SyncDirSummary {
+      actions: vec![],
+      unchanged: plan.unchanged,
+    };
+    let push_options = options.push_options();
+    let mut session: Option<SyncSession> = None;
+
+    for action in plan.actions {
+      debug!("sync: {}", action);
+      let result = match action {
+        SyncAction::Delete { ref remote_path } => remove(self, remote_path).map(|_| 0),
+        SyncAction::Create {
+          ref local_path,
+          ref remote_path,
+        }
+        | SyncAction::Update {
+          ref local_path,
+          ref remote_path,
+        } => SyncSession::ensure_open(&mut session, self).and_then(|session| {
+          session.set_compression(options.compression);
+          push_file(self, session, local_path, remote_path, &push_options)
+        }),
+      };
+
+      if let Err(ref err) = result {
+        debug!("sync failed: {}: {}", action.remote_path(), err);
+      }
+
+      summary.actions.push(SyncActionResult { action, result });
+    }
+
+    // every action is done, a failing `QUIT` changes none of their results
+    if let Some(session) = session {
+      if let Err(err) = session.quit() {
+        debug!("sync: failed to end the sync session: {}", err);
+      }
+    }
+
+    Ok(summary)
+  }
+}
+
+struct Planner<'a> {
+  conn: &'a mut AdbConnection,
+  session: &'a SyncSession,
+  follow_symlinks: bool,
+  // (dev, ino) of the directories being walked, to detect symlink loops
+  ancestors: Vec<(u64, u64)>,
+  plan: Plan<'a>,
+}
+
+impl<'a> Planner<'a> {
+  fn walk(&mut self, local_dir: &Path, remote_dir: &str, list_remote: bool) -> AdbResult<()> {
+    let remote: HashMap<String, RemoteMetadata> = if list_remote {
+      self
+        .session
+        .list(remote_dir)?
+        .into_iter()
+        .map(|entry| (entry.name().to_string(), *entry.metadata()))
+        .collect()
+    } else {
+      HashMap::new()
+    };
+
+    let mut entries = fs::read_dir(local_dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    let id = local_file_id(&fs::metadata(local_dir)?);
+    self.ancestors.extend(id);
+    let mut local = vec![];
+    for entry in entries {
+      let name = entry.file_name().to_string_lossy().into_owned();
+      let metadata = if self.follow_symlinks {
+        fs::metadata(entry.path())
+      } else {
+        fs::symlink_metadata(entry.path())
+      };
+      let entry = match metadata {
+        Ok(ref metadata)
+          if metadata.is_dir()
+            && local_file_id(metadata).is_some_and(|id| self.ancestors.contains(&id)) =>
+        {
+          debug!("sync: skipping symlink loop {}", entry.path().display());
+          LocalEntry::Loop
+        }
+        Ok(ref metadata) => LocalEntry::from_metadata(metadata),
+        Err(err) => {
+          debug!("sync: {}: {}", entry.path().display(), err);
+          LocalEntry::Unreadable
+        }
+      };
+      local.push((name, entry));
+    }
+
+    let conn = &mut *self.conn;
+    let subdirs = self.plan.plan_dir(
+      local_dir,
+      remote_dir,
+      &local,
+      &remote,
+      |local_path, remote_path| Ok(readlink(conn, remote_path)? == local_link_target(local_path)?),
+    )?;
+    for (local_dir, remote_dir, list_remote) in subdirs {
+      self.walk(&local_dir, &remote_dir, list_remote)?;
+    }
+    if id.is_some() {
+      self.ancestors.pop();
+    }
+
+    Ok(())
+  }
+}
+
+/// What planning needs to know of a local directory entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LocalEntry {
+  Dir,
+  File {
+    len: u64,
+    mtime: i64,
+  },
+  Symlink,
+  /// A followed symlink to a directory being walked, left alone.
+  Loop,
+  /// Its metadata could not be read, e.g. a dangling symlink being followed.
+  /// It is pushed anyway, to fail with its own error.
+  Unreadable,
+}
+
+impl LocalEntry {
+  fn from_metadata(metadata: &fs::Metadata) -> Self {
+    if metadata.file_type().is_symlink() {
+      LocalEntry::Symlink
+    } else if metadata.is_dir() {
+      LocalEntry::Dir
+    } else {
+      LocalEntry::File {
+        len: metadata.len(),
+        mtime: local_mtime(metadata),
+      }
+    }
+  }
+}
+
+/// The actions planned so far, decided from directory listings alone.
+struct Plan<'a> {
+  options: &'a SyncDirOptions,
+  deletes: Vec<SyncAction>,
+  pushes: Vec<SyncAction>,
+  unchanged: usize,
+  // files of the same size, to be compared by checksum
+  candidates: Vec<(PathBuf, String)>,
+}
+
+impl<'a> Plan<'a> {
+  fn new(options: &'a SyncDirOptions) -> Self {
+    Plan {
+      options,
+      deletes: vec![],
+      pushes: vec![],
+      unchanged: 0,
+      candidates: vec![],
+    }
+  }
+
+  /// Plans the entries of `local_dir`, sorted by name, against those listed
+  /// in `remote_dir`. `links_match` tells whether a local symlink and the
+  /// remote one at the same path point to the same target.
+  ///
+  /// Returns the subdirectories to plan next, with whether the remote one
+  /// exists and is to be listed.
+  fn plan_dir<F>(
+    &mut self,
+    local_dir: &Path,
+    remote_dir: &str,
+    local: &[(String, LocalEntry)],
+    remote: &HashMap<String, RemoteMetadata>,
+    mut links_match: F,
+  ) -> AdbResult<Vec<(PathBuf, String, bool)>>
+  where
+    F: FnMut(&Path, &str) -> AdbResult<bool>,
+  {
+    let mut subdirs = vec![];
+
+    for (name, entry) in local {
+      let local_path = local_dir.join(name);
+      let remote_path = format!("{}/{}", remote_dir, name);
+      let remote = remote.get(name);
+
+      match *entry {
+        LocalEntry::Symlink => {
+          if remote.is_some_and(|m| m.is_symlink()) && links_match(&local_path, &remote_path)? {
+            self.unchanged += 1;
+          } else {
+            self.plan_symlink(local_path, remote_path, remote);
+          }
+        }
+        LocalEntry::Dir => {
+          let replace = self.options.delete && remote.is_some_and(|m| !m.is_dir());
+          if replace {
+            self.delete(&remote_path);
+          }
+          subdirs.push((local_path, remote_path, remote.is_some() && !replace));
+        }
+        LocalEntry::Loop => {}
+        LocalEntry::Unreadable => self.pushes.push(match remote {
+          None => SyncAction::Create {
+            local_path,
+            remote_path,
+          },
+          Some(_) => SyncAction::Update {
+            local_path,
+            remote_path,
+          },
+        }),
+        LocalEntry::File { len, mtime } => match remote {
+          None => self.pushes.push(SyncAction::Create {
+            local_path,
+            remote_path,
+          }),
+          Some(remote) if remote.is_dir() && self.options.delete => {
+            self.delete(&remote_path);
+            self.pushes.push(SyncAction::Create {
+              local_path,
+              remote_path,
+            });
+          }
+          Some(remote) => {
+            if !remote.is_file() || remote.len() != len {
+              self.pushes.push(SyncAction::Update {
+                local_path,
+                remote_path,
+              });
+            } else if self.options.checksum {
+              self.candidates.push((local_path, remote_path));
+            } else if remote.mtime() != mtime {
+              self.pushes.push(SyncAction::Update {
+                local_path,
+                remote_path,
+              });
+            } else {
+              self.unchanged += 1;
+            }
+          }
+        },
+      }
+    }
+
+    if self.options.delete {
+      let local_names = local
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<HashSet<_>>();
+      let mut missing = remote
+        .keys()
+        .filter(|name| !local_names.contains(name.as_str()))
+        .collect::<Vec<_>>();
+      missing.sort();
+      for name in missing {
+        self.delete(&format!("{}/{}", remote_dir, name));
+      }
+    }
+
+    Ok(subdirs)
+  }
+
+  // a symlink that differs from the remote entry, if any
+  fn plan_symlink(
+    &mut self,
+    local_path: PathBuf,
+    remote_path: String,
+    remote: Option<&RemoteMetadata>,
+  ) {
+    match remote {
+      None => {}
+      // adbd does not replace symlinks
+      Some(remote) if remote.is_symlink() => self.delete(&remote_path),
+      Some(remote) if remote.is_dir() && self.options.delete => self.delete(&remote_path),
+      Some(_) => {
+        self.pushes.push(SyncAction::Update {
+          local_path,
+          remote_path,
+        });
+        return;
+      }
+    }
+    self.pushes.push(SyncAction::Create {
+      local_path,
+      remote_path,
+    });
+  }
+
+  fn delete(&mut self, remote_path: &str) {
+    self.deletes.push(SyncAction::Delete {
+      remote_path: remote_path.to_string(),
+    });
+  }
+}
+
+fn local_mtime(metadata: &fs::Metadata) -> i64 {
+  metadata
+    .modified()
+    .ok()
+    .and_then(|mtime| mtime.duration_since(SystemTime::UNIX_EPOCH).ok())
+    .map(|d| d.as_secs() as i64)
+    .unwrap_or(0)
+}
+
+fn local_sha256(local_path: &Path) -> AdbResult<String> {
+  let mut hasher = Sha256::new();
+  io::copy(&mut File::open(local_path)?, &mut hasher)?;
+  Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Hashes remote files with `sha256sum`, batching as many paths per command
+/// as fit in one packet. Files that could not be hashed are left out.
+fn remote_sha256(
+  conn: &mut AdbConnection,
+  remote_paths: &[String],
+) -> AdbResult<HashMap<String, String>> {
+  let max_len = conn.max_data_len() - "exec:".len() - 1;
+  let mut hashes = HashMap::new();
+  let mut remaining = remote_paths;
+
+  while !remaining.is_empty() {
+    let mut n = 1;
+    while n < remaining.len() && sha256sum(&remaining[..n + 1]).command_line().len() <= max_len {
+      n += 1;
+    }
+
+    let output = conn.exec(&sha256sum(&remaining[..n]).command_line())?;
+    hashes.extend(parse_sha256sum(&String::from_utf8_lossy(&output)));
+
+    remaining = &remaining[n..];
+  }
+
+  Ok(hashes)
+}
+
+// maps the paths in `sha256sum` output to their hashes, from lines of
+// "<hash>  <path>"; errors are reported on other lines
+fn parse_sha256sum(output: &str) -> HashMap<String, String> {
+  output
+    .lines()
+    .filter(|line| line.len() > 66 && line.is_char_boundary(64) && &line[64..66] == "  ")
+    .map(|line| (line[66..].to_string(), line[..64].to_string()))
+    .collect()
+}
+
+fn sha256sum(remote_paths: &[String]) -> ShellCommand {
+  ShellCommand::new("sha256sum")
+    .arg("--")
+    .args(remote_paths.iter().cloned())
+}
+
+/// Deletes a remote file or directory tree with `rm -rf`.
+fn remove(conn: &mut AdbConnection, remote_path: &str) -> AdbResult<()> {
+  let cmd = ShellCommand::new("rm")
+    .arg("-rf")
+    .arg("--")
+    .arg(remote_path);
+  let output = conn.exec(&cmd.command_line())?;
+  // `rm -f` is silent unless it fails
+  let message = String::from_utf8_lossy(&output).trim().to_string();
+  if !message.is_empty() {
+    return Err(AdbError::Fail(message));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::stat::{S_IFDIR, S_IFLNK, S_IFREG};
+
+  const FILE: LocalEntry = LocalEntry::File { len: 3, mtime: 100 };
+
+  fn remote_file(len: u64, mtime: i64) -> RemoteMetadata {
+    RemoteMetadata::new(S_IFREG | 0o644, len, mtime)
+  }
+
+  fn remote_dir() -> RemoteMetadata {
+    RemoteMetadata::new(S_IFDIR | 0o755, 0, 0)
+  }
+
+  fn remote_link() -> RemoteMetadata {
+    RemoteMetadata::new(S_IFLNK | 0o777, 0, 0)
+  }
+
+  fn create(name: &str) -> SyncAction {
+    SyncAction::Create {
+      local_path: Path::new("l").join(name),
+      remote_path: format!("r/{}", name),
+    }
+  }
+
+  fn update(name: &str) -> SyncAction {
+    SyncAction::Update {
+      local_path: Path::new("l").join(name),
+      remote_path: format!("r/{}", name),
+    }
+  }
+
+  fn delete(name: &str) -> SyncAction {
+    SyncAction::Delete {
+      remote_path: format!("r/{}", name),
+    }
+  }
+
+  struct Planned {
+    actions: Vec<SyncAction>,
+    unchanged: usize,
+    candidates: Vec<(PathBuf, String)>,
+    subdirs: Vec<(PathBuf, String, bool)>,
+  }
+
+  // plans the local dir "l" against the remote dir "r"; symlinks named in
+  // `same_links` point to the same target on both sides
+  fn plan(
+    options: &SyncDirOptions,
+    local: &[(&str, LocalEntry)],
+    remote: &[(&str, RemoteMetadata)],
+    same_links: &[&str],
+  ) -> Planned {
+    let local = local
+      .iter()
+      .map(|&(name, entry)| (name.to_string(), entry))
+      .collect::<Vec<_>>();
+    let remote = remote
+      .iter()
+      .map(|&(name, metadata)| (name.to_string(), metadata))
+      .collect();
+    let mut plan = Plan::new(options);
+    let subdirs = plan
+      .plan_dir(
+        Path::new("l"),
+        "r",
+        &local,
+        &remote,
+        |local_path, remote_path| {
+          let name = &remote_path["r/".len()..];
+          assert_eq!(local_path, Path::new("l").join(name));
+          Ok(same_links.contains(&name))
+        },
+      )
+      .unwrap();
+    let mut actions = plan.deletes;
+    actions.append(&mut plan.pushes);
+    Planned {
+      actions,
+      unchanged: plan.unchanged,
+      candidates: plan.candidates,
+      subdirs,
+    }
+  }
+
+  #[test]
+  fn files() {
+    let options = SyncDirOptions::default();
+    let cases: &[(Option<RemoteMetadata>, Vec<SyncAction>, usize)] = &[
+      (None, vec![create("f")], 0),
+      (Some(remote_file(3, 100)), vec![], 1),
+      (Some(remote_file(3, 99)), vec![update("f")], 0),
+      (Some(remote_file(4, 100)), vec![update("f")], 0),
+      (Some(remote_link()), vec![update("f")], 0),
+      // without `delete`, adbd is left to fail on the directory
+      (Some(remote_dir()), vec![update("f")], 0),
+    ];
+    for (remote, actions, unchanged) in cases {
+      let remote = remote.iter().map(|m| ("f", *m)).collect::<Vec<_>>();
+      let planned = plan(&options, &[("f", FILE)], &remote, &[]);
+      assert_eq!(&planned.actions, actions, "remote {:?}", remote);
+      assert_eq!(planned.unchanged, *unchanged, "remote {:?}", remote);
+    }
+  }
+
+  #[test]
+  fn checksum_candidates() {
+    let options = SyncDirOptions {
+      checksum: true,
+      ..Default::default()
+    };
+    let planned = plan(
+      &options,
+      &[("a", FILE), ("b", FILE), ("c", FILE)],
+      &[("a", remote_file(3, 1)), ("b", remote_file(4, 100))],
+      &[],
+    );
+    assert_eq!(planned.actions, vec![update("b"), create("c")]);
+    assert_eq!(
+      planned.candidates,
+      vec![(Path::new("l").join("a"), "r/a".to_string())]
+    );
+    assert_eq!(planned.unchanged, 0);
+  }
+
+  #[test]
+  fn file_replaces_dir_with_delete() {
+    let options = SyncDirOptions {
+      delete: true,
+      ..Default::default()
+    };
+    let planned = plan(&options, &[("f", FILE)], &[("f", remote_dir())], &[]);
+    assert_eq!(planned.actions, vec![delete("f"), create("f")]);
+  }
+
+  #[test]
+  fn dirs() {
+    let delete_options = SyncDirOptions {
+      delete: true,
+      ..Default::default()
+    };
+    let cases: &[(bool, Option<RemoteMetadata>, Vec<SyncAction>, bool)] = &[
+      (false, None, vec![], false),
+      (false, Some(remote_dir()), vec![], true),
+      (false, Some(remote_file(3, 100)), vec![], true),
+      (true, Some(remote_file(3, 100)), vec![delete("d")], false),
+      (true, Some(remote_link()), vec![delete("d")], false),
+      (true, Some(remote_dir()), vec![], true),
+    ];
+    for (delete, remote, actions, list_remote) in cases {
+      let options = if *delete {
+        delete_options.clone()
+      } else {
+        SyncDirOptions::default()
+      };
+      let remote = remote.iter().map(|m| ("d", *m)).collect::<Vec<_>>();
+      let planned = plan(&options, &[("d", LocalEntry::Dir)], &remote, &[]);
+      assert_eq!(&planned.actions, actions, "remote {:?}", remote);
+      assert_eq!(
+        planned.subdirs,
+        vec![(Path::new("l").join("d"), "r/d".to_string(), *list_remote)],
+        "remote {:?}",
+        remote
+      );
+    }
+  }
+
+  #[test]
+  fn symlinks() {
+    // (remote, whether it points to the same target, actions, unchanged)
+    let cases: &[(Option<RemoteMetadata>, bool, Vec<SyncAction>, usize)] = &[
+      (None, false, vec![create("s")], 0),
+      (Some(remote_link()), true, vec![], 1),
+      // adbd does not replace a symlink, it is deleted first
+      (
+        Some(remote_link()),
+        false,
+        vec![delete("s"), create("s")],
+        0,
+      ),
+      (Some(remote_file(3, 100)), false, vec![update("s")], 0),
+      (Some(remote_dir()), false, vec![update("s")], 0),
+    ];
+    for (remote, same_target, actions, unchanged) in cases {
+      let remote = remote.iter().map(|m| ("s", *m)).collect::<Vec<_>>();
+      let same_links: &[&str] = if *same_target { &["s"] } else { &[] };
+      let planned = plan(
+        &SyncDirOptions::default(),
+        &[("s", LocalEntry::Symlink)],
+        &remote,
+        same_links,
+      );
+      assert_eq!(&planned.actions, actions, "remote {:?}", remote);
+      assert_eq!(planned.unchanged, *unchanged, "remote {:?}", remote);
+    }
+
+    let options = SyncDirOptions {
+      delete: true,
+      ..Default::default()
+    };
+    let planned = plan(
+      &options,
+      &[("s", LocalEntry::Symlink)],
+      &[("s", remote_dir())],
+      &[],
+    );
+    assert_eq!(planned.actions, vec![delete("s"), create("s")]);
+  }
+
+  #[test]
+  fn loops_and_unreadable_entries() {
+    let options = SyncDirOptions {
+      delete: true,
+      ..Default::default()
+    };
+    let planned = plan(
+      &options,
+      &[
+        ("loop", LocalEntry::Loop),
+        ("new", LocalEntry::Unreadable),
+        ("old", LocalEntry::Unreadable),
+      ],
+      &[("loop", remote_dir()), ("old", remote_file(3, 100))],
+      &[],
+    );
+    assert_eq!(planned.actions, vec![create("new"), update("old")]);
+    assert!(planned.subdirs.is_empty());
+  }
+
+  #[test]
+  fn deletes_missing_entries() {
+    let remote = [
+      ("f", remote_file(3, 100)),
+      ("z", remote_file(1, 1)),
+      ("gone", remote_dir()),
+    ];
+    let planned = plan(&SyncDirOptions::default(), &[("f", FILE)], &remote, &[]);
+    assert!(planned.actions.is_empty());
+
+    let options = SyncDirOptions {
+      delete: true,
+      ..Default::default()
+    };
+    let planned = plan(&options, &[("f", FILE)], &remote, &[]);
+    assert_eq!(planned.actions, vec![delete("gone"), delete("z")]);
+    assert_eq!(planned.unchanged, 1);
+  }
+
+  #[test]
+  fn parse_sha256sum_output() {
+    let hash = "a".repeat(64);
+    let other = "0123456789abcdef".repeat(4);
+    let output = format!(
+      "{h}  /sdcard/a\n{o}  /sdcard/with  two spaces\nsha256sum: /sdcard/x: No such file or directory\n{h} /sdcard/one_space\n{h}  \nshort  line\n\u{e9}{h}  /sdcard/shifted\n",
+      h = hash,
+      o = other
+    );
+    let hashes = parse_sha256sum(&output);
+    assert_eq!(hashes.len(), 2);
+    assert_eq!(hashes["/sdcard/a"], hash);
+    assert_eq!(hashes["/sdcard/with  two spaces"], other);
+    assert!(parse_sha256sum("").is_empty());
+  }
+}
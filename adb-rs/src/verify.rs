@@ -0,0 +1,96 @@
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::io::{self, Read};
+use std::rc::Rc;
+
+use super::client::*;
+use super::exec::exec_output;
+use super::shell::ShellCommand;
+use crate::result::*;
+
+/// Hashes of the bytes read through a `HashingReader`.
+///
+/// Both are computed, as which one the device can compute is only known
+/// afterwards.
+#[derive(Clone, Default)]
+pub(crate) struct Hashes {
+  sha256: Sha256,
+  md5: Md5,
+}
+
+/// Hashes the bytes read through it into a shared `Hashes`.
+pub(crate) struct HashingReader<R> {
+  inner: R,
+  hashes: Rc<RefCell<Hashes>>,
+}
+
+impl<R: Read> HashingReader<R> {
+  pub(crate) fn new(inner: R) -> (Self, Rc<RefCell<Hashes>>) {
+    let hashes = Rc::new(RefCell::new(Hashes::default()));
+    let r = HashingReader {
+      inner,
+      hashes: hashes.clone(),
+    };
+    (r, hashes)
+  }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let n = self.inner.read(buf)?;
+    let mut hashes = self.hashes.borrow_mut();
+    hashes.sha256.update(&buf[..n]);
+    hashes.md5.update(&buf[..n]);
+    Ok(n)
+  }
+}
+
+/// Compares `hashes` with a hash of `remote_path` computed on the device.
+///
+/// SHA-256 is preferred, falling back to MD5 and to the `toybox` applets on
+/// devices without standalone `sha256sum`/`md5sum`.
+pub(crate) fn verify(conn: &AdbConnection, remote_path: &str, hashes: &Hashes) -> AdbResult<()> {
+  let cmds = [
+    ShellCommand::new("sha256sum"),
+    ShellCommand::new("toybox").arg("sha256sum"),
+    ShellCommand::new("md5sum"),
+    ShellCommand::new("toybox").arg("md5sum"),
+  ];
+  // the first command that succeeds prints the hash, only the last error is
+  // kept to be reported
+  let line = cmds
+    .iter()
+    .map(|cmd| cmd.clone().arg("--").arg(remote_path).command_line())
+    .collect::<Vec<_>>()
+    .join(" 2>/dev/null || ");
+
+  let output = exec_output(conn, &line)?;
+  let output = String::from_utf8_lossy(&output);
+  let actual = output
+    .split_whitespace()
+    .next()
+    .filter(|hash| hash.bytes().all(|b| b.is_ascii_hexdigit()))
+    .unwrap_or("");
+  let expected = match actual.len() {
+    64 => format!("{:x}", hashes.sha256.clone().finalize()),
+    32 => format!("{:x}", hashes.md5.clone().finalize()),
+    _ => {
+      return Err(AdbError::Fail(format!(
+        "failed to hash remote file '{}': {}",
+        remote_path,
+        output.trim()
+      )))
+    }
+  };
+
+  debug!("verify: {}: {}", remote_path, actual);
+  if !actual.eq_ignore_ascii_case(&expected) {
+    return Err(AdbError::ChecksumMismatch {
+      path: remote_path.to_string(),
+      expected,
+      actual: actual.to_string(),
+    });
+  }
+  Ok(())
+}